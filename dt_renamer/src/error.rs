@@ -1,17 +1,63 @@
 use std::io;
 
+use crate::rename_tree::RenameStage;
+
 #[derive(Debug)]
 pub enum Error {
     WalkerError(dt_walker::Error),
     NotDirectory(String),
     NotFile(String),
+    MissingSources(Vec<String>),
     DuplicateFileError(String),
+    DuplicateDestinationError(String),
+    DestinationIsDirectory(String),
+    ParentIsFile(String),
+    PathEscapesRoot(String),
     RenameError(io::Error),
+    CopyError(io::Error),
+    LinkError(io::Error),
+    CreateDirError(io::Error),
     CanonicalizeError(io::Error),
     ReadDirError(io::Error),
     ReadDirEntryError(io::Error),
+    ReadFileError(io::Error),
+    WriteFileError(io::Error),
+    SyncDirError(io::Error),
+    ReadInputError(io::Error),
+    MetadataError(io::Error),
     CannotIdentifyFileName,
     InsertIndexTooLarge,
+    ZeroInsertInterval,
+    InvalidRegex(String),
+    InvalidLookupTable(String),
+    InvalidManifest(String),
+    ParseError {
+        position: usize,
+        message: String,
+    },
     VariableNotDefined(String),
     CannotIdentifyFileExtension,
+    FileContext {
+        path: String,
+        source: Box<Error>,
+    },
+    ContradictoryOperations(String),
+    RollbackError {
+        source: Box<Error>,
+        rollback_errors: Vec<Error>,
+    },
+    StagedRenameError {
+        stage: RenameStage,
+        source: Box<Error>,
+        cleanup_errors: Vec<Error>,
+    },
+    LogError(io::Error),
+    NoFilesMatched,
+    #[cfg(feature = "free_space")]
+    InsufficientSpace {
+        needed: u64,
+        available: u64,
+    },
+    #[cfg(feature = "watch")]
+    WatchError(String),
 }
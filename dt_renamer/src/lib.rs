@@ -4,6 +4,8 @@ mod rename_tree;
 // pub mod rules;
 mod operation_engine;
 mod script;
+#[cfg(feature = "watch")]
+mod watch;
 
 pub use operation_engine::*;
 pub use rename_tree::*;
@@ -1,18 +1,34 @@
 use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+#[cfg(feature = "datetime")]
+use std::time::SystemTime;
 
 use crate::error::Error;
-use crate::operations::{DirOperation, FileOperation};
-use crate::rename_tree::{Dir, File};
+use crate::operations::{DirOperation, Expression, FileOperation, MatchRule};
+use crate::rename_tree::{Dir, File, MetadataErrorPolicy};
 
 #[derive(Debug, Default, Clone)]
 pub struct OperationEngine {
     global_index: usize,
     local_index: usize,
     variables: HashMap<String, String>,
+    dir_scoped_variables: bool,
+    tracing: bool,
+    metadata_error_policy: MetadataErrorPolicy,
+    full_path_names: bool,
+    case_insensitive: bool,
+    name_root: Option<PathBuf>,
+    first_pass: HashMap<PathBuf, PathBuf>,
+    fallback_name: Option<Box<dyn Expression>>,
+    name_missing: bool,
+    #[cfg(feature = "datetime")]
+    started: Option<SystemTime>,
     dir_operations: Vec<Box<dyn DirOperation>>,
     file_operations: Vec<Box<dyn FileOperation>>,
     current_file: usize,
+    current_dir: PathBuf,
     files: Vec<File>,
+    completed: Vec<File>,
 }
 
 impl OperationEngine {
@@ -24,31 +40,149 @@ impl OperationEngine {
             global_index: 0,
             local_index: 0,
             variables: Default::default(),
+            dir_scoped_variables: false,
+            tracing: false,
+            metadata_error_policy: Default::default(),
+            full_path_names: false,
+            case_insensitive: false,
+            name_root: None,
+            first_pass: Default::default(),
+            fallback_name: None,
+            name_missing: false,
+            #[cfg(feature = "datetime")]
+            started: Some(SystemTime::now()),
             dir_operations,
             file_operations,
             current_file: 0,
+            current_dir: Default::default(),
             files: Default::default(),
+            completed: Default::default(),
         };
     }
 
+    /// Clear user assigned variables at the start of each directory so they
+    /// cannot leak between directories. Built-in variables are unaffected.
+    pub fn with_dir_scoped_variables(mut self, scoped: bool) -> Self {
+        self.dir_scoped_variables = scoped;
+
+        return self;
+    }
+
+    /// Record the file name after every file operation, see `RenameTree::explain`.
+    pub fn with_tracing(mut self, tracing: bool) -> Self {
+        self.tracing = tracing;
+
+        return self;
+    }
+
+    /// Let `FileNameExpr`, `FileStemExpr`, `FileExtensionExpr`,
+    /// `SetNameOperation` and `SetStemOperation` work on the destination path
+    /// relative to the directory root instead of only the file name, so folder
+    /// and file names can be renamed together.
+    pub fn with_full_path_names(mut self, full_path_names: bool) -> Self {
+        self.full_path_names = full_path_names;
+
+        return self;
+    }
+
+    /// Compare literal match rules and `ReplaceExpr`/`InsertExpr` markers
+    /// case-insensitively. Regex rules keep their own flags.
+    pub fn with_case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+
+        return self;
+    }
+
+    pub fn with_metadata_error_policy(mut self, policy: MetadataErrorPolicy) -> Self {
+        self.metadata_error_policy = policy;
+
+        return self;
+    }
+
+    /// Name a file with `fallback` when its operations compute an empty name
+    /// or none at all, e.g. because the metadata they read was missing.
+    pub fn with_fallback_name(mut self, fallback: Option<Box<dyn Expression>>) -> Self {
+        self.fallback_name = fallback;
+
+        return self;
+    }
+
+    pub(crate) fn with_file_operations(mut self, ops: Vec<Box<dyn FileOperation>>) -> Self {
+        self.file_operations = ops;
+
+        return self;
+    }
+
+    /// Source to destination pairs computed by an earlier pass, read by
+    /// `SiblingNameExpr`.
+    pub(crate) fn with_first_pass(mut self, first_pass: HashMap<PathBuf, PathBuf>) -> Self {
+        self.first_pass = first_pass;
+
+        return self;
+    }
+
+    /// A copy of this engine's operations and settings without any of its run
+    /// state, for processing another batch of files the same way.
+    pub(crate) fn for_new_run(&self) -> Self {
+        return Self::new(self.dir_operations.clone(), self.file_operations.clone())
+            .with_dir_scoped_variables(self.dir_scoped_variables)
+            .with_tracing(self.tracing)
+            .with_metadata_error_policy(self.metadata_error_policy)
+            .with_full_path_names(self.full_path_names)
+            .with_case_insensitive(self.case_insensitive)
+            .with_fallback_name(self.fallback_name.clone());
+    }
+
     pub fn process_dir(&mut self, mut dir: Dir) -> Result<(), Error> {
         self.local_index = 0;
 
+        if self.dir_scoped_variables {
+            self.variables.clear();
+        }
+
+        // Walked sources are canonical, so the root has to be as well for
+        // paths to be made relative to it.
+        let root = dir.path.canonicalize().unwrap_or_else(|_| dir.path.clone());
+
+        self.current_dir = root.clone();
+        self.name_root = self.full_path_names.then(|| root.clone());
+
         let mut files = std::mem::take(&mut dir.contents);
 
-        for op in std::mem::take(&mut self.dir_operations) {
-            op.execute(self, &mut files)?;
+        for f in &mut files {
+            f.root = Some(root.clone());
         }
 
-        for op in dir.dir_ops {
+        let (after_files, mut before_files): (Vec<_>, Vec<_>) = self
+            .dir_operations
+            .clone()
+            .into_iter()
+            .chain(dir.dir_ops)
+            .partition(|op| op.runs_after_files());
+
+        before_files.sort_by_key(|op| op.runs_after_ordering());
+
+        for op in before_files {
             op.execute(self, &mut files)?;
         }
 
-        return self.run_files(files);
+        self.run_files(files)?;
+
+        for op in after_files {
+            let mut files = std::mem::take(&mut self.files);
+            let res = op.execute(self, &mut files);
+
+            self.files = files;
+            res?;
+        }
+
+        return Ok(());
     }
 
     fn run_files(&mut self, files: Vec<File>) -> Result<(), Error> {
+        self.completed.append(&mut self.files);
         self.files = files;
+        self.current_file = 0;
 
         while self.current_file < self.files.len() {
             self.run_file()?;
@@ -61,52 +195,650 @@ impl OperationEngine {
 
     pub fn process_file(&mut self, file: File) -> Result<(), Error> {
         self.local_index = 0;
+        self.name_root = None;
+        self.completed.append(&mut self.files);
         self.files = vec![file];
         self.current_file = 0;
 
         return self.run_file();
     }
 
+    pub fn transform_name(
+        &mut self,
+        name: &str,
+        ops: &[Box<dyn FileOperation>],
+    ) -> Result<String, Error> {
+        let res = self.process_file(File::new(name).with_ops(&mut ops.to_vec()));
+
+        // The synthetic file is dropped even when the ops fail, so it never
+        // ends up among the engine's files.
+        let file = self.files.pop().unwrap();
+
+        res?;
+
+        return file
+            .destination
+            .file_name()
+            .and_then(|s| s.to_str())
+            .map(|s| s.to_string())
+            .ok_or(Error::CannotIdentifyFileName);
+    }
+
     fn run_file(&mut self) -> Result<(), Error> {
+        return match self.apply_file_ops() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(Error::FileContext {
+                path: self.current_file().source.display().to_string(),
+                source: Box::new(e),
+            }),
+        };
+    }
+
+    fn apply_file_ops(&mut self) -> Result<(), Error> {
+        self.name_missing = false;
+
         let ops = self.file_operations.clone();
 
         for op in ops {
             op.execute(self)?;
+            self.trace_op(op.as_ref());
         }
 
         let ops = self.current_file().ops.clone();
 
         for op in ops {
             op.execute(self)?;
+            self.trace_op(op.as_ref());
         }
 
+        self.apply_fallback_name()?;
+
         self.global_index += 1;
         self.local_index += 1;
 
         return Ok(());
     }
 
+    fn apply_fallback_name(&mut self) -> Result<(), Error> {
+        if !self.name_missing {
+            return Ok(());
+        }
+
+        let Some(fallback) = self.fallback_name.clone() else {
+            return Ok(());
+        };
+
+        if let Some(name) = fallback.execute(self)?.filter(|n| !n.is_empty()) {
+            self.set_current_name(&name)?;
+        }
+
+        return Ok(());
+    }
+
+    fn trace_op(&mut self, op: &dyn FileOperation) {
+        if !self.tracing {
+            return;
+        }
+
+        let file = self.current_file();
+        let name = file
+            .destination
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        file.trace.push((op.name().to_string(), name));
+    }
+
     pub(crate) fn set_local_index(&mut self, index: usize) {
         self.local_index = index;
     }
 
+    pub(crate) fn set_global_index(&mut self, index: usize) {
+        self.global_index = index;
+    }
+
     pub(crate) fn set_variable(&mut self, var_name: String, value: String) {
         self.variables.insert(var_name, value);
     }
 
+    pub fn variable_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.variables.keys().map(|k| k.as_str()).collect();
+        names.sort_unstable();
+
+        return names;
+    }
+
+    pub fn clear_variables(&mut self) {
+        self.variables.clear();
+    }
+
     pub(crate) fn get_variable(&self, var_name: &str) -> Option<String> {
         return match var_name {
             "global_index" => Some(self.global_index.to_string()),
             "local_index" => Some(self.local_index.to_string()),
-            s => self.variables.get(s).map(|s| s.clone()),
+            s => self
+                .files
+                .get(self.current_file)
+                .and_then(|f| f.variables.get(s))
+                .or_else(|| self.variables.get(s))
+                .cloned(),
+        };
+    }
+
+    /// Evaluate an expression with `file` temporarily treated as the current
+    /// file, for directory operations that need per-file values.
+    pub(crate) fn evaluate_for_file(
+        &mut self,
+        file: &mut File,
+        expr: &dyn Expression,
+    ) -> Result<Option<String>, Error> {
+        let previous = self.current_file;
+
+        self.files
+            .push(std::mem::replace(file, File::new(PathBuf::new())));
+        self.current_file = self.files.len() - 1;
+
+        let res = expr.execute(self);
+
+        *file = self.files.pop().unwrap();
+        self.current_file = previous;
+
+        return res;
+    }
+
+    pub(crate) fn first_pass_destination(&self, source: &Path) -> Option<&PathBuf> {
+        return self.first_pass.get(source);
+    }
+
+    pub(crate) fn metadata_error_policy(&self) -> MetadataErrorPolicy {
+        return self.metadata_error_policy;
+    }
+
+    /// The time this engine was created, shared by every file in the run.
+    #[cfg(feature = "datetime")]
+    pub(crate) fn started(&mut self) -> SystemTime {
+        return *self.started.get_or_insert_with(SystemTime::now);
+    }
+
+    pub(crate) fn case_insensitive(&self) -> bool {
+        return self.case_insensitive;
+    }
+
+    pub(crate) fn resolve(&self, rule: &MatchRule, input: &String) -> bool {
+        return rule.resolve_with_case(input, self.case_insensitive);
+    }
+
+    /// The current file's name, or its path below the directory root when
+    /// full path names are enabled.
+    pub(crate) fn current_name(&mut self) -> Option<String> {
+        let root = self.name_root.clone();
+        let destination = &self.current_file().destination;
+
+        let name = match root.as_ref().and_then(|r| destination.strip_prefix(r).ok()) {
+            Some(relative) => relative.as_os_str(),
+            None => destination.file_name()?,
+        };
+
+        return name.to_str().map(|s| s.to_string());
+    }
+
+    pub(crate) fn set_current_name(&mut self, name: &str) -> Result<(), Error> {
+        // An empty name is left for the fallback name to replace.
+        if name.is_empty() && self.fallback_name.is_some() {
+            self.name_missing = true;
+
+            return Ok(());
+        }
+
+        self.name_missing = false;
+
+        let root = self.name_root.clone();
+        let destination = &mut self.current_file().destination;
+
+        let Some(root) = root.filter(|r| destination.starts_with(r)) else {
+            destination.set_file_name(name);
+
+            return Ok(());
         };
+
+        let relative = Path::new(name);
+
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(Error::PathEscapesRoot(name.to_string()));
+        }
+
+        *destination = root.join(relative);
+
+        return Ok(());
+    }
+
+    /// Record that the current file's name was computed as nothing, so the
+    /// fallback name applies unless a later operation sets one.
+    pub(crate) fn mark_name_missing(&mut self) {
+        self.name_missing = true;
+    }
+
+    pub(crate) fn current_dir(&self) -> &PathBuf {
+        return &self.current_dir;
     }
 
     pub(crate) fn current_file(&mut self) -> &mut File {
         return &mut self.files[self.current_file];
     }
 
-    pub fn into_files(self) -> Vec<File> {
-        return self.files;
+    /// Move the destinations of the current directory's files from their `Dir`
+    /// root to `to`, keeping their relative structure. Fails with
+    /// `PathEscapesRoot` if a destination is no longer under its root.
+    pub(crate) fn rebase_destinations(&mut self, to: &Path) -> Result<(), Error> {
+        for file in &mut self.files {
+            let relative = file
+                .root
+                .as_ref()
+                .and_then(|root| file.destination.strip_prefix(root).ok())
+                .ok_or_else(|| Error::PathEscapesRoot(file.destination.display().to_string()))?;
+
+            file.destination = to.join(relative);
+        }
+
+        return Ok(());
+    }
+
+    pub fn into_files(mut self) -> Vec<File> {
+        self.completed.append(&mut self.files);
+
+        return self.completed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::directory::ClearVariablesOperation;
+    use crate::operations::expressions::{
+        AssignVariableExpr, FileExtensionExpr, FileNameExpr, FileStemExpr, InsertExpr, ReplaceExpr,
+        ToUpperCaseExpr, VariableExpr,
+    };
+    use crate::operations::file::{
+        NoOpOperation, SetExtensionOperation, SetNameOperation, SetStemOperation,
+    };
+    use crate::operations::supporting_objects::{Position, Selection};
+    use crate::RenameTree;
+
+    #[test]
+    fn test_dir_scoped_variables_1() {
+        let mut engine =
+            OperationEngine::new(Vec::new(), Vec::new()).with_dir_scoped_variables(true);
+
+        let mut dir_a = Dir::new("a", false);
+        dir_a.contents = vec![File::new("a/one.txt").with_op(NoOpOperation::new(
+            AssignVariableExpr::new("v".to_string(), "x".into()).into(),
+        ))];
+
+        engine.process_dir(dir_a).unwrap();
+
+        assert_eq!(engine.get_variable("v"), Some("x".to_string()));
+
+        engine.process_dir(Dir::new("b", false)).unwrap();
+
+        assert_eq!(engine.get_variable("v"), None);
+        assert_eq!(engine.get_variable("global_index"), Some("1".to_string()));
+    }
+
+    #[test]
+    fn test_dir_scoped_variables_2() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        let mut dir_a = Dir::new("a", false);
+        dir_a.contents = vec![File::new("a/one.txt").with_op(NoOpOperation::new(
+            AssignVariableExpr::new("v".to_string(), "x".into()).into(),
+        ))];
+
+        engine.process_dir(dir_a).unwrap();
+        engine.process_dir(Dir::new("b", false)).unwrap();
+
+        assert_eq!(engine.get_variable("v"), Some("x".to_string()));
+    }
+
+    #[test]
+    fn test_file_context_1() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        let mut dir = Dir::new("a", false);
+        dir.contents = vec![
+            File::new("a/one.txt"),
+            File::new("a/two.txt").with_op(SetNameOperation::new(
+                VariableExpr::new("missing".to_string()).into(),
+            )),
+        ];
+
+        match engine.process_dir(dir) {
+            Err(Error::FileContext { path, source }) => {
+                assert_eq!(path, "a/two.txt");
+                assert!(matches!(*source, Error::VariableNotDefined(_)));
+            }
+            r => panic!("Expected a file context error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_tracing_1() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new()).with_tracing(true);
+
+        engine
+            .process_file(
+                File::new("a/one.txt")
+                    .with_op(SetNameOperation::new("two.txt".into()))
+                    .with_op(SetExtensionOperation::new(FileStemExpr::new().into())),
+            )
+            .unwrap();
+
+        let explained = RenameTree::from(engine).explain();
+
+        assert_eq!(explained.len(), 1);
+        assert_eq!(explained[0].0, std::path::PathBuf::from("a/one.txt"));
+        assert_eq!(
+            explained[0].1,
+            vec![
+                ("SetNameOperation".to_string(), "two.txt".to_string()),
+                ("SetExtensionOperation".to_string(), "two.two".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_transform_name_1() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        let name = engine
+            .transform_name(
+                "a.txt",
+                &[
+                    SetStemOperation::new(
+                        InsertExpr::new(Position::End, FileStemExpr::new().into(), "_b".into())
+                            .into(),
+                    )
+                    .into(),
+                    SetNameOperation::new(
+                        ReplaceExpr::new(
+                            FileNameExpr::new().into(),
+                            Selection::All,
+                            "_".into(),
+                            "-".into(),
+                        )
+                        .into(),
+                    )
+                    .into(),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(name, "a-b.txt");
+        assert!(engine.into_files().is_empty());
+    }
+
+    #[test]
+    fn test_rebase_destinations_1() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        let mut dir = Dir::new("a", false);
+        dir.contents = vec![File::new("a/one.txt")];
+
+        engine.process_dir(dir).unwrap();
+        engine.files[0].destination = PathBuf::from("b/one.txt");
+
+        assert!(matches!(
+            engine.rebase_destinations(Path::new("out")),
+            Err(Error::PathEscapesRoot(p)) if p == "b/one.txt"
+        ));
+    }
+
+    #[test]
+    fn test_transform_name_2() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        let mut dir = Dir::new("a", false);
+        dir.contents = vec![File::new("a/one.txt")];
+
+        engine.process_dir(dir).unwrap();
+
+        assert!(matches!(
+            engine.transform_name(
+                "b.txt",
+                &[SetNameOperation::new(VariableExpr::new("missing".to_string()).into()).into()],
+            ),
+            Err(Error::FileContext { .. })
+        ));
+        assert_eq!(
+            engine
+                .into_files()
+                .iter()
+                .map(|f| f.source.display().to_string())
+                .collect::<Vec<_>>(),
+            vec!["a/one.txt"]
+        );
+    }
+
+    #[test]
+    fn test_clear_variables_1() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        engine.set_variable("b".to_string(), "2".to_string());
+        engine.set_variable("a".to_string(), "1".to_string());
+
+        assert_eq!(engine.variable_names(), vec!["a", "b"]);
+
+        engine.clear_variables();
+
+        assert!(engine.variable_names().is_empty());
+        assert_eq!(engine.get_variable("a"), None);
+    }
+
+    #[test]
+    fn test_clear_variables_operation_1() {
+        let mut engine =
+            OperationEngine::new(vec![ClearVariablesOperation::new().into()], Vec::new());
+
+        engine.set_variable("a".to_string(), "1".to_string());
+        engine.process_dir(Dir::new("a", false)).unwrap();
+
+        assert!(engine.variable_names().is_empty());
+    }
+
+    mod process_dir {
+        use super::*;
+        use crate::operations::directory::RemoveOperation;
+        use crate::operations::supporting_objects::PathMatchMode;
+
+        fn dir(path: &str, names: &[&str]) -> Dir {
+            let mut dir = Dir::new(path, false);
+            dir.contents = names
+                .iter()
+                .map(|n| File::new(Path::new(path).join(n)))
+                .collect();
+
+            return dir;
+        }
+
+        #[test]
+        fn test_dir_ops_every_dir() {
+            let mut engine = OperationEngine::new(
+                vec![Box::new(RemoveOperation::new(
+                    MatchRule::BeginsWith("skip".to_string()),
+                    PathMatchMode::FileName,
+                ))],
+                Vec::new(),
+            );
+
+            engine
+                .process_dir(dir("a", &["keep.txt", "skip.txt"]))
+                .unwrap();
+            engine
+                .process_dir(dir("b", &["keep.txt", "skip.txt"]))
+                .unwrap();
+
+            assert_eq!(
+                engine
+                    .into_files()
+                    .iter()
+                    .map(|f| f.source.clone())
+                    .collect::<Vec<_>>(),
+                vec![
+                    Path::new("a").join("keep.txt"),
+                    Path::new("b").join("keep.txt")
+                ]
+            );
+        }
+
+        #[test]
+        fn test_keeps_files_of_earlier_dirs() {
+            let mut engine = OperationEngine::new(
+                Vec::new(),
+                vec![Box::new(SetStemOperation::new(
+                    VariableExpr::new("global_index".to_string()).into(),
+                ))],
+            );
+
+            engine.process_dir(dir("a", &["x.txt", "y.txt"])).unwrap();
+            engine.process_dir(dir("b", &["z.txt"])).unwrap();
+
+            assert_eq!(
+                engine
+                    .into_files()
+                    .iter()
+                    .map(|f| f.destination.clone())
+                    .collect::<Vec<_>>(),
+                vec![
+                    Path::new("a").join("0.txt"),
+                    Path::new("a").join("1.txt"),
+                    Path::new("b").join("2.txt"),
+                ]
+            );
+        }
+    }
+
+    mod full_path_names {
+        use super::*;
+        use crate::RTBuilder;
+        use std::fs;
+
+        fn replace_old(root: &Path, name: Box<dyn Expression>) -> Result<RenameTree, Error> {
+            return RTBuilder::new()
+                .with_directory(Dir::new(root, true))
+                .with_file_op(SetNameOperation::new(name))
+                .with_full_path_names(true)
+                .build_tree();
+        }
+
+        #[test]
+        fn test_full_path_names_1() {
+            let dir = tempfile::tempdir().unwrap();
+            let root = dir.path().canonicalize().unwrap();
+
+            fs::create_dir_all(root.join("old_photos")).unwrap();
+            fs::write(root.join("old_photos/old_beach.jpg"), "").unwrap();
+            fs::write(root.join("old_notes.txt"), "").unwrap();
+
+            let tree = replace_old(
+                &root,
+                ReplaceExpr::new(
+                    FileNameExpr::new().into(),
+                    Selection::All,
+                    "old".into(),
+                    "new".into(),
+                )
+                .into(),
+            )
+            .unwrap();
+
+            let mut planned = tree.planned();
+            planned.sort();
+
+            assert_eq!(
+                planned,
+                vec![
+                    (root.join("old_notes.txt"), root.join("new_notes.txt")),
+                    (
+                        root.join("old_photos/old_beach.jpg"),
+                        root.join("new_photos/new_beach.jpg")
+                    ),
+                ]
+            );
+        }
+
+        #[test]
+        fn test_full_path_names_2() {
+            let dir = tempfile::tempdir().unwrap();
+            let root = dir.path().canonicalize().unwrap();
+
+            fs::write(root.join("a.txt"), "").unwrap();
+
+            for escape in ["../a.txt", "/tmp/a.txt", "x/../../a.txt"] {
+                match replace_old(&root, escape.into()) {
+                    Err(Error::FileContext { source, .. }) => {
+                        assert!(matches!(*source, Error::PathEscapesRoot(_)))
+                    }
+                    r => panic!(
+                        "Expected path escapes root, got {:?}",
+                        r.map(|t| t.planned())
+                    ),
+                }
+            }
+        }
+
+        #[test]
+        fn test_full_path_names_relative_dir() {
+            let dir = tempfile::tempdir_in(".").unwrap();
+            let root = dir
+                .path()
+                .strip_prefix(std::env::current_dir().unwrap())
+                .unwrap();
+
+            fs::create_dir_all(root.join("old_photos")).unwrap();
+            fs::write(root.join("old_photos/old_beach.jpg"), "").unwrap();
+
+            let planned = RTBuilder::new()
+                .with_directory(Dir::new(root, true))
+                .with_file_op(SetStemOperation::new(
+                    ReplaceExpr::new(
+                        FileStemExpr::new().into(),
+                        Selection::All,
+                        "old".into(),
+                        "new".into(),
+                    )
+                    .into(),
+                ))
+                .with_file_op(SetExtensionOperation::new(
+                    ToUpperCaseExpr::new(FileExtensionExpr::new().into()).into(),
+                ))
+                .with_full_path_names(true)
+                .build_tree()
+                .unwrap()
+                .planned();
+
+            let root = root.canonicalize().unwrap();
+
+            assert_eq!(
+                planned,
+                vec![(
+                    root.join("old_photos/old_beach.jpg"),
+                    root.join("new_photos/new_beach.JPG")
+                )]
+            );
+        }
+
+        #[test]
+        fn test_full_path_names_3() {
+            assert_eq!(
+                OperationEngine::new(Vec::new(), Vec::new())
+                    .with_full_path_names(true)
+                    .transform_name("a/b.txt", &[SetNameOperation::new("c.txt".into()).into()])
+                    .unwrap(),
+                "c.txt"
+            );
+        }
     }
 }
@@ -1,24 +1,116 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
 use crate::error::Error;
-use crate::operations::supporting_objects::SortDirection;
-use crate::operations::{DirOperation, MatchRule};
-use crate::{clone_dyn, define_opexp_skeleton, File, OperationEngine};
+use crate::operations::supporting_objects::{MetadataKey, PathMatchMode, SortDirection};
+use crate::operations::{DirOperation, Expression, MatchRule};
+use crate::{clone_dyn, define_opexp_skeleton, File, MetadataErrorPolicy, OperationEngine};
 
 define_opexp_skeleton!(sort_operation, direction: SortDirection);
-define_opexp_skeleton!(remove_operation, rule: MatchRule);
-define_opexp_skeleton!(include_only_operation, rule: MatchRule);
+define_opexp_skeleton!(sort_by_metadata_operation, key: MetadataKey, direction: SortDirection);
+define_opexp_skeleton!(remove_operation, rule: MatchRule, mode: PathMatchMode);
+define_opexp_skeleton!(include_only_operation, rule: MatchRule, mode: PathMatchMode);
 define_opexp_skeleton!(offset_local_index_operation, offset: usize);
+define_opexp_skeleton!(offset_global_index_operation, offset: usize);
+define_opexp_skeleton!(regroup_operation, key: Box<dyn Expression>);
+define_opexp_skeleton!(enumerate_operation, var: String, start: i64);
+define_opexp_skeleton!(resetting_index_operation, key: Box<dyn Expression>, var: String, width: usize);
+define_opexp_skeleton!(resequence_operation, width: usize, start: usize, keep_extension: bool);
+define_opexp_skeleton!(disambiguate_with_parent_operation, separator: String);
+define_opexp_skeleton!(set_variable_operation, var: String, value: Box<dyn Expression>);
+define_opexp_skeleton!(clear_variables_operation);
+
+fn match_subject(file: &File, mode: PathMatchMode) -> Result<String, Error> {
+    return match mode {
+        PathMatchMode::FileName => Ok(file
+            .destination
+            .file_name()
+            .ok_or(Error::CannotIdentifyFileName)?
+            .to_str()
+            .ok_or(Error::CannotIdentifyFileName)?
+            .to_string()),
+        PathMatchMode::FullPath => Ok(file
+            .destination
+            .to_str()
+            .ok_or(Error::CannotIdentifyFileName)?
+            .to_string()),
+    };
+}
 
+// Files with equal destinations are ordered by their source path so that
+// index based names (e.g. `LocalIndex`) are reproducible between runs.
 impl DirOperation for SortOperation {
     fn execute(&self, _engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
+        match self.direction {
+            SortDirection::Ascending => input.sort_by(|a, b| {
+                a.destination
+                    .cmp(&b.destination)
+                    .then_with(|| a.source.cmp(&b.source))
+            }),
+            SortDirection::Descending => input.sort_by(|a, b| {
+                b.destination
+                    .cmp(&a.destination)
+                    .then_with(|| a.source.cmp(&b.source))
+            }),
+        }
+
+        return Ok(());
+    }
+
+    clone_dyn!(DirOperation);
+}
+
+fn metadata_sort_key(path: &Path, key: MetadataKey) -> io::Result<u128> {
+    let metadata = fs::metadata(path)?;
+
+    return Ok(match key {
+        MetadataKey::Size => metadata.len() as u128,
+        MetadataKey::Modified => metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0),
+    });
+}
+
+// How unreadable files are handled is decided by the engine's
+// `MetadataErrorPolicy`.
+impl DirOperation for SortByMetadataOperation {
+    fn execute(&self, engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
+        let mut keyed = Vec::with_capacity(input.len());
+
+        for f in input.drain(0..) {
+            let key = match metadata_sort_key(&f.source, self.key) {
+                Ok(key) => key,
+                Err(e) => match engine.metadata_error_policy() {
+                    MetadataErrorPolicy::Error => {
+                        return Err(Error::FileContext {
+                            path: f.source.display().to_string(),
+                            source: Box::new(Error::MetadataError(e)),
+                        })
+                    }
+                    MetadataErrorPolicy::Skip => continue,
+                    MetadataErrorPolicy::Default => 0,
+                },
+            };
+
+            keyed.push((key, f));
+        }
+
         match self.direction {
             SortDirection::Ascending => {
-                input.sort_unstable_by(|a, b| a.destination.cmp(&b.destination))
+                keyed.sort_by(|(a, fa), (b, fb)| a.cmp(b).then_with(|| fa.source.cmp(&fb.source)))
             }
             SortDirection::Descending => {
-                input.sort_unstable_by(|a, b| b.destination.cmp(&a.destination))
+                keyed.sort_by(|(a, fa), (b, fb)| b.cmp(a).then_with(|| fa.source.cmp(&fb.source)))
             }
         }
 
+        input.extend(keyed.into_iter().map(|(_, f)| f));
+
         return Ok(());
     }
 
@@ -26,18 +118,11 @@ impl DirOperation for SortOperation {
 }
 
 impl DirOperation for RemoveOperation {
-    fn execute(&self, _engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
+    fn execute(&self, engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
         let mut res = Vec::new();
 
         for f in input.drain(0..) {
-            if !self.rule.resolve(
-                &f.destination
-                    .file_name()
-                    .ok_or(Error::CannotIdentifyFileName)?
-                    .to_str()
-                    .ok_or(Error::CannotIdentifyFileName)?
-                    .to_string(),
-            ) {
+            if !engine.resolve(&self.rule, &match_subject(&f, self.mode)?) {
                 res.push(f);
             }
         }
@@ -51,18 +136,11 @@ impl DirOperation for RemoveOperation {
 }
 
 impl DirOperation for IncludeOnlyOperation {
-    fn execute(&self, _engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
+    fn execute(&self, engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
         let mut res = Vec::new();
 
         for f in input.drain(0..) {
-            if self.rule.resolve(
-                &f.destination
-                    .file_name()
-                    .ok_or(Error::CannotIdentifyFileName)?
-                    .to_str()
-                    .ok_or(Error::CannotIdentifyFileName)?
-                    .to_string(),
-            ) {
+            if engine.resolve(&self.rule, &match_subject(&f, self.mode)?) {
                 res.push(f);
             }
         }
@@ -84,3 +162,749 @@ impl DirOperation for OffsetLocalIndexOperation {
 
     clone_dyn!(DirOperation);
 }
+
+impl DirOperation for OffsetGlobalIndexOperation {
+    fn execute(&self, engine: &mut OperationEngine, _input: &mut Vec<File>) -> Result<(), Error> {
+        engine.set_global_index(self.offset);
+
+        return Ok(());
+    }
+
+    clone_dyn!(DirOperation);
+}
+
+impl DirOperation for RegroupOperation {
+    fn execute(&self, engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
+        for f in input.iter_mut() {
+            let Some(key) = engine.evaluate_for_file(f, self.key.as_ref())? else {
+                continue;
+            };
+
+            // A key may add directories but never leave the file's own one.
+            if !Path::new(&key)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(Error::PathEscapesRoot(key));
+            }
+
+            let file_name = f
+                .destination
+                .file_name()
+                .ok_or(Error::CannotIdentifyFileName)?
+                .to_owned();
+
+            f.destination.set_file_name(key);
+            f.destination.push(file_name);
+        }
+
+        return Ok(());
+    }
+
+    clone_dyn!(DirOperation);
+}
+
+impl DirOperation for EnumerateOperation {
+    fn execute(&self, _engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
+        for (f, n) in input.iter_mut().zip(self.start..) {
+            f.variables.insert(self.var.clone(), n.to_string());
+        }
+
+        return Ok(());
+    }
+
+    clone_dyn!(DirOperation);
+
+    fn runs_after_ordering(&self) -> bool {
+        return true;
+    }
+}
+
+// Files keep the order the other dir operations left them in and are renamed
+// to a contiguous run starting at `start`.
+impl DirOperation for ResequenceOperation {
+    fn execute(&self, _engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
+        for (f, n) in input.iter_mut().zip(self.start..) {
+            let mut name = format!("{:0width$}", n, width = self.width);
+
+            if self.keep_extension {
+                if let Some(extension) = f.destination.extension() {
+                    name.push('.');
+                    name.push_str(
+                        extension
+                            .to_str()
+                            .ok_or(Error::CannotIdentifyFileExtension)?,
+                    );
+                }
+            }
+
+            f.destination.set_file_name(name);
+        }
+
+        return Ok(());
+    }
+
+    clone_dyn!(DirOperation);
+
+    fn runs_after_ordering(&self) -> bool {
+        return true;
+    }
+}
+
+impl DirOperation for ResettingIndexOperation {
+    fn execute(&self, engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
+        let mut previous = None;
+        let mut n = 0;
+
+        for f in input.iter_mut() {
+            let key = engine.evaluate_for_file(f, self.key.as_ref())?;
+
+            if previous.as_ref() != Some(&key) {
+                n = 0;
+                previous = Some(key);
+            }
+
+            n += 1;
+
+            f.variables.insert(
+                self.var.clone(),
+                format!("{:0width$}", n, width = self.width),
+            );
+        }
+
+        return Ok(());
+    }
+
+    clone_dyn!(DirOperation);
+}
+
+// Runs after the file operations, so only files whose final destination path
+// is shared with another file in the batch are prefixed with their source's
+// parent directory name.
+impl DirOperation for DisambiguateWithParentOperation {
+    fn execute(&self, _engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error> {
+        let mut counts: HashMap<PathBuf, usize> = HashMap::new();
+
+        for f in input.iter() {
+            *counts.entry(f.destination.clone()).or_default() += 1;
+        }
+
+        for f in input.iter_mut() {
+            if counts[&f.destination] < 2 {
+                continue;
+            }
+
+            let name = f
+                .destination
+                .file_name()
+                .ok_or(Error::CannotIdentifyFileName)?
+                .to_owned();
+
+            let Some(parent) = f.source.parent().and_then(|p| p.file_name()) else {
+                continue;
+            };
+
+            let mut prefixed = parent.to_owned();
+            prefixed.push(&self.separator);
+            prefixed.push(&name);
+
+            f.destination.set_file_name(prefixed);
+        }
+
+        return Ok(());
+    }
+
+    clone_dyn!(DirOperation);
+
+    fn runs_after_files(&self) -> bool {
+        return true;
+    }
+}
+
+impl DirOperation for SetVariableOperation {
+    fn execute(&self, engine: &mut OperationEngine, _input: &mut Vec<File>) -> Result<(), Error> {
+        let mut dir = File::new(engine.current_dir().clone());
+
+        if let Some(value) = engine.evaluate_for_file(&mut dir, self.value.as_ref())? {
+            engine.set_variable(self.var.clone(), value);
+        }
+
+        return Ok(());
+    }
+
+    clone_dyn!(DirOperation);
+}
+
+impl DirOperation for ClearVariablesOperation {
+    fn execute(&self, engine: &mut OperationEngine, _input: &mut Vec<File>) -> Result<(), Error> {
+        engine.clear_variables();
+
+        return Ok(());
+    }
+
+    clone_dyn!(DirOperation);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, MAIN_SEPARATOR_STR};
+
+    use super::*;
+    use crate::operations::expressions::{
+        CombineExpr, FileExtensionExpr, FileNameExpr, ParentDirNameExpr, RightExpr, VariableExpr,
+    };
+    use crate::operations::file::SetNameOperation;
+    use crate::{Dir, RTBuilder};
+
+    fn nested_fixture(root: &Path) {
+        fs::create_dir_all(root.join("drafts")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("drafts").join("b.txt"), "b").unwrap();
+    }
+
+    fn file_names(op: impl DirOperation + 'static, root: &Path) -> Vec<String> {
+        let mut names: Vec<String> = RTBuilder::new()
+            .with_directory(Dir::new(root, true).with_dir_op(op))
+            .build_tree()
+            .unwrap()
+            .dry_run()
+            .unwrap()
+            .into_iter()
+            .map(|r| {
+                Path::new(&r.destination_path_string().unwrap())
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+
+        names.sort();
+
+        return names;
+    }
+
+    #[test]
+    fn test_resequence_1() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+        let mut files: Vec<File> = ["001.jpg", "003.jpg", "007.jpg", "012.jpg", "cover.jpg"]
+            .iter()
+            .map(|s| File::new(*s))
+            .collect();
+
+        ResequenceOperation::new(3, 1, true)
+            .execute(&mut engine, &mut files)
+            .unwrap();
+
+        assert_eq!(
+            files
+                .iter()
+                .map(|f| (
+                    f.source.display().to_string(),
+                    f.destination.display().to_string()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("001.jpg".to_string(), "001.jpg".to_string()),
+                ("003.jpg".to_string(), "002.jpg".to_string()),
+                ("007.jpg".to_string(), "003.jpg".to_string()),
+                ("012.jpg".to_string(), "004.jpg".to_string()),
+                ("cover.jpg".to_string(), "005.jpg".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resequence_2() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+        let mut files: Vec<File> = ["track 10.flac", "track 9.flac"]
+            .iter()
+            .map(|s| File::new(*s))
+            .collect();
+
+        ResequenceOperation::new(0, 0, false)
+            .execute(&mut engine, &mut files)
+            .unwrap();
+
+        assert_eq!(files[0].source.display().to_string(), "track 10.flac");
+        assert_eq!(files[0].destination.display().to_string(), "0");
+        assert_eq!(files[1].destination.display().to_string(), "1");
+    }
+
+    #[test]
+    fn test_resequence_after_sort() {
+        let mut engine = OperationEngine::new(
+            vec![ResequenceOperation::new(3, 1, true).into()],
+            Vec::new(),
+        );
+
+        let mut dir =
+            Dir::new("dir", false).with_dir_op(SortOperation::new(SortDirection::Descending));
+        dir.contents = vec![
+            File::new("dir/001.jpg"),
+            File::new("dir/007.jpg"),
+            File::new("dir/003.jpg"),
+        ];
+
+        engine.process_dir(dir).unwrap();
+
+        assert_eq!(
+            engine
+                .into_files()
+                .iter()
+                .map(|f| (f.source.display().to_string(), f.destination_path_string()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("dir/007.jpg".to_string(), "dir/001.jpg".to_string()),
+                ("dir/003.jpg".to_string(), "dir/002.jpg".to_string()),
+                ("dir/001.jpg".to_string(), "dir/003.jpg".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disambiguate_with_parent_1() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+        let mut files: Vec<File> = ["beach/photo.jpg", "city/photo.jpg", "city/unique.jpg"]
+            .iter()
+            .map(|s| {
+                let mut f = File::new(*s);
+                f.destination = Path::new("out").join(f.source.file_name().unwrap());
+
+                return f;
+            })
+            .collect();
+
+        DisambiguateWithParentOperation::new("_".to_string())
+            .execute(&mut engine, &mut files)
+            .unwrap();
+
+        assert_eq!(
+            files
+                .iter()
+                .map(|f| f.destination.display().to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new("out")
+                    .join("beach_photo.jpg")
+                    .display()
+                    .to_string(),
+                Path::new("out")
+                    .join("city_photo.jpg")
+                    .display()
+                    .to_string(),
+                Path::new("out").join("unique.jpg").display().to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disambiguate_with_parent_2() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::write(root.join("a").join("photo.jpg"), "a").unwrap();
+        fs::write(root.join("b").join("photo.jpg"), "b").unwrap();
+        fs::write(root.join("b").join("unique.jpg"), "c").unwrap();
+
+        // Moves every file up into the root.
+        let mut planned = RTBuilder::new()
+            .with_directory(
+                Dir::new(&root, true)
+                    .with_dir_op(DisambiguateWithParentOperation::new("_".to_string())),
+            )
+            .with_file_op(SetNameOperation::new(
+                RightExpr::new(FileNameExpr::new().into(), MAIN_SEPARATOR_STR.into(), false).into(),
+            ))
+            .with_full_path_names(true)
+            .build_tree()
+            .unwrap()
+            .planned();
+        planned.sort();
+
+        assert_eq!(
+            planned.into_iter().map(|(_, d)| d).collect::<Vec<_>>(),
+            vec![
+                root.join("a_photo.jpg"),
+                root.join("b_photo.jpg"),
+                root.join("unique.jpg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_ties_by_source() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        let mut files: Vec<File> = ["b/photo.jpg", "c/photo.jpg", "a/photo.jpg"]
+            .iter()
+            .map(|s| {
+                let mut f = File::new(*s);
+                f.destination = "out/photo.jpg".into();
+
+                return f;
+            })
+            .collect();
+
+        SortOperation::new(SortDirection::Ascending)
+            .execute(&mut engine, &mut files)
+            .unwrap();
+
+        assert_eq!(
+            files
+                .iter()
+                .map(|f| f.source.display().to_string())
+                .collect::<Vec<_>>(),
+            vec!["a/photo.jpg", "b/photo.jpg", "c/photo.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_resetting_index() {
+        let mut engine = OperationEngine::new(
+            Vec::new(),
+            vec![SetNameOperation::new(
+                CombineExpr::new(vec![
+                    ParentDirNameExpr::new(1).into(),
+                    "_".into(),
+                    VariableExpr::new("n".to_string()).into(),
+                    ".".into(),
+                    FileExtensionExpr::new().into(),
+                ])
+                .strict()
+                .into(),
+            )
+            .into()],
+        );
+
+        let mut dir = Dir::new("dir", true)
+            .with_dir_op(SortOperation::new(SortDirection::Ascending))
+            .with_dir_op(ResettingIndexOperation::new(
+                ParentDirNameExpr::new(1).into(),
+                "n".to_string(),
+                2,
+            ));
+        dir.contents = vec![
+            File::new("dir/beach/b.jpg"),
+            File::new("dir/city/a.jpg"),
+            File::new("dir/beach/a.jpg"),
+            File::new("dir/city/b.jpg"),
+            File::new("dir/beach/c.jpg"),
+        ];
+
+        engine.process_dir(dir).unwrap();
+
+        assert_eq!(
+            engine
+                .into_files()
+                .iter()
+                .map(|f| f.destination_path_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "dir/beach/beach_01.jpg",
+                "dir/beach/beach_02.jpg",
+                "dir/beach/beach_03.jpg",
+                "dir/city/city_01.jpg",
+                "dir/city/city_02.jpg",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_enumerate_after_sort() {
+        let mut engine = OperationEngine::new(
+            Vec::new(),
+            vec![SetNameOperation::new(
+                CombineExpr::new(vec![
+                    VariableExpr::new("n".to_string()).into(),
+                    "_".into(),
+                    FileNameExpr::new().into(),
+                ])
+                .strict()
+                .into(),
+            )
+            .into()],
+        );
+
+        let mut dir = Dir::new("dir", false)
+            .with_dir_op(SortOperation::new(SortDirection::Ascending))
+            .with_dir_op(EnumerateOperation::new("n".to_string(), 1));
+        dir.contents = vec![
+            File::new("dir/c.txt"),
+            File::new("dir/a.txt"),
+            File::new("dir/b.txt"),
+        ];
+
+        engine.process_dir(dir).unwrap();
+
+        assert_eq!(
+            engine
+                .into_files()
+                .iter()
+                .map(|f| f.destination_path_string())
+                .collect::<Vec<_>>(),
+            vec!["dir/1_a.txt", "dir/2_b.txt", "dir/3_c.txt"]
+        );
+    }
+
+    #[test]
+    fn test_enumerate_before_dir_sort() {
+        let mut engine = OperationEngine::new(
+            vec![EnumerateOperation::new("n".to_string(), 1).into()],
+            vec![SetNameOperation::new(
+                CombineExpr::new(vec![
+                    VariableExpr::new("n".to_string()).into(),
+                    "_".into(),
+                    FileNameExpr::new().into(),
+                ])
+                .strict()
+                .into(),
+            )
+            .into()],
+        );
+
+        let mut dir =
+            Dir::new("dir", false).with_dir_op(SortOperation::new(SortDirection::Ascending));
+        dir.contents = vec![
+            File::new("dir/c.txt"),
+            File::new("dir/a.txt"),
+            File::new("dir/b.txt"),
+        ];
+
+        engine.process_dir(dir).unwrap();
+
+        assert_eq!(
+            engine
+                .into_files()
+                .iter()
+                .map(|f| f.destination_path_string())
+                .collect::<Vec<_>>(),
+            vec!["dir/1_a.txt", "dir/2_b.txt", "dir/3_c.txt"]
+        );
+    }
+
+    #[test]
+    fn test_set_variable() {
+        let mut engine = OperationEngine::new(
+            Vec::new(),
+            vec![SetNameOperation::new(
+                CombineExpr::new(vec![
+                    VariableExpr::new("folder".to_string()).into(),
+                    "_".into(),
+                    FileNameExpr::new().into(),
+                ])
+                .strict()
+                .into(),
+            )
+            .into()],
+        );
+
+        let mut dir = Dir::new("photos/holiday", false).with_dir_op(SetVariableOperation::new(
+            "folder".to_string(),
+            FileNameExpr::new().into(),
+        ));
+        dir.contents = vec![File::new("photos/holiday/a.jpg")];
+
+        engine.process_dir(dir).unwrap();
+
+        assert_eq!(
+            engine.into_files()[0].destination_path_string(),
+            "photos/holiday/holiday_a.jpg"
+        );
+    }
+
+    #[test]
+    fn test_offset_global_index() {
+        let mut engine = OperationEngine::new(
+            vec![OffsetGlobalIndexOperation::new(10).into()],
+            vec![
+                SetNameOperation::new(VariableExpr::new("global_index".to_string()).into()).into(),
+            ],
+        );
+
+        let mut dir = Dir::new("dir", false);
+        dir.contents = vec![File::new("dir/a.txt"), File::new("dir/b.txt")];
+
+        engine.process_dir(dir).unwrap();
+
+        assert_eq!(
+            engine
+                .into_files()
+                .iter()
+                .map(|f| f.destination_path_string())
+                .collect::<Vec<_>>(),
+            vec!["dir/10", "dir/11"]
+        );
+    }
+
+    #[test]
+    fn test_regroup_escaping_key() {
+        for key in ["..", "../out", "a/../../out", "/tmp"] {
+            let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+            let mut files = vec![File::new(Path::new("dir").join("a.txt"))];
+
+            assert!(matches!(
+                RegroupOperation::new(key.into()).execute(&mut engine, &mut files),
+                Err(Error::PathEscapesRoot(k)) if k == key
+            ));
+            assert_eq!(files[0].destination, Path::new("dir").join("a.txt"));
+        }
+    }
+
+    #[test]
+    fn test_regroup_by_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        for name in ["a.jpg", "b.png", "c.jpg", "d"] {
+            fs::write(root.join(name), name).unwrap();
+        }
+
+        let mut results: Vec<String> = RTBuilder::new()
+            .with_directory(
+                Dir::new(&root, false)
+                    .with_dir_op(RegroupOperation::new(FileExtensionExpr::new().into())),
+            )
+            .with_create_dirs(true)
+            .build_tree()
+            .unwrap()
+            .run()
+            .unwrap()
+            .into_iter()
+            .map(|r| {
+                Path::new(&r.destination_path_string().unwrap())
+                    .strip_prefix(&root)
+                    .unwrap()
+                    .display()
+                    .to_string()
+            })
+            .collect();
+
+        results.sort();
+
+        assert_eq!(
+            results,
+            vec![
+                "d".to_string(),
+                Path::new("jpg").join("a.jpg").display().to_string(),
+                Path::new("jpg").join("c.jpg").display().to_string(),
+                Path::new("png").join("b.png").display().to_string(),
+            ]
+        );
+        assert!(root.join("jpg").join("a.jpg").is_file());
+        assert!(root.join("png").join("b.png").is_file());
+    }
+
+    #[test]
+    fn test_remove_full_path() {
+        let dir = tempfile::tempdir().unwrap();
+        nested_fixture(dir.path());
+
+        assert_eq!(
+            file_names(
+                RemoveOperation::new(
+                    MatchRule::Contains("drafts".to_string()),
+                    PathMatchMode::FullPath
+                ),
+                dir.path()
+            ),
+            vec!["a.txt"]
+        );
+    }
+
+    #[test]
+    fn test_remove_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+        nested_fixture(dir.path());
+
+        assert_eq!(
+            file_names(
+                RemoveOperation::new(
+                    MatchRule::Contains("drafts".to_string()),
+                    PathMatchMode::FileName
+                ),
+                dir.path()
+            ),
+            vec!["a.txt", "b.txt"]
+        );
+    }
+
+    #[test]
+    fn test_include_only_full_path() {
+        let dir = tempfile::tempdir().unwrap();
+        nested_fixture(dir.path());
+
+        assert_eq!(
+            file_names(
+                IncludeOnlyOperation::new(
+                    MatchRule::Contains("drafts".to_string()),
+                    PathMatchMode::FullPath
+                ),
+                dir.path()
+            ),
+            vec!["b.txt"]
+        );
+    }
+
+    mod sort_by_metadata {
+        use super::*;
+
+        fn sorted(root: &Path, policy: MetadataErrorPolicy) -> Result<Vec<String>, Error> {
+            fs::write(root.join("big.txt"), "0123456789").unwrap();
+            fs::write(root.join("small.txt"), "0").unwrap();
+
+            // A file that vanished after the walk fails its metadata lookup.
+            let mut files: Vec<File> = ["big.txt", "missing.txt", "small.txt"]
+                .iter()
+                .map(|name| File::new(root.join(name)))
+                .collect();
+
+            let mut engine =
+                OperationEngine::new(Vec::new(), Vec::new()).with_metadata_error_policy(policy);
+
+            SortByMetadataOperation::new(MetadataKey::Size, SortDirection::Ascending)
+                .execute(&mut engine, &mut files)?;
+
+            return Ok(files
+                .iter()
+                .map(|f| f.source.file_name().unwrap().to_str().unwrap().to_string())
+                .collect());
+        }
+
+        #[test]
+        fn test_sort_by_metadata_error() {
+            let dir = tempfile::tempdir().unwrap();
+
+            match sorted(dir.path(), MetadataErrorPolicy::Error) {
+                Err(Error::FileContext { path, source }) => {
+                    assert!(path.ends_with("missing.txt"));
+                    assert!(matches!(*source, Error::MetadataError(_)));
+                }
+                r => panic!("Expected a metadata error, got {:?}", r),
+            }
+        }
+
+        #[test]
+        fn test_sort_by_metadata_skip() {
+            let dir = tempfile::tempdir().unwrap();
+
+            assert_eq!(
+                sorted(dir.path(), MetadataErrorPolicy::Skip).unwrap(),
+                vec!["small.txt", "big.txt"]
+            );
+        }
+
+        #[test]
+        fn test_sort_by_metadata_default() {
+            let dir = tempfile::tempdir().unwrap();
+
+            assert_eq!(
+                sorted(dir.path(), MetadataErrorPolicy::Default).unwrap(),
+                vec!["missing.txt", "small.txt", "big.txt"]
+            );
+        }
+    }
+}
@@ -1,9 +1,21 @@
+#[cfg(any(feature = "datetime", feature = "exif"))]
+use chrono::NaiveDateTime;
+#[cfg(feature = "datetime")]
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+#[cfg(feature = "regex_match")]
+use std::sync::LazyLock;
+
 use convert_case::{Case, Casing};
 use itertools::Itertools;
 #[cfg(feature = "regex_match")]
 use regex::Regex;
 
 use crate::error::Error;
+#[cfg(feature = "regex_match")]
+use crate::operations::supporting_objects::TrackNumberMode;
 use crate::operations::supporting_objects::{Position, Selection};
 use crate::operations::{Expression, MatchRule};
 use crate::OperationEngine;
@@ -11,11 +23,30 @@ use crate::{clone_dyn, define_opexp_skeleton};
 
 #[cfg(feature = "regex_match")]
 define_opexp_skeleton!(regex_match_expr, regex: Regex, input: Box<dyn Expression>);
+#[cfg(feature = "regex_match")]
+define_opexp_skeleton!(season_episode_expr, input: Box<dyn Expression>, output_format: String);
+#[cfg(feature = "regex_match")]
+define_opexp_skeleton!(track_number_expr, input: Box<dyn Expression>, mode: TrackNumberMode);
+#[cfg(feature = "regex_match")]
+define_opexp_skeleton!(capture_to_variable_expr, regex: Regex, group: usize, var: String, input: Box<dyn Expression>);
+#[cfg(feature = "datetime")]
+define_opexp_skeleton!(parse_date_expr, input: Box<dyn Expression>, input_format: String, output_format: String);
+#[cfg(feature = "datetime")]
+define_opexp_skeleton!(now_expr, format: String, utc: bool);
+#[cfg(feature = "exif")]
+define_opexp_skeleton!(exif_date_expr, format: String);
+#[cfg(feature = "xattr")]
+define_opexp_skeleton!(xattr_expr, name: String);
 
 define_opexp_skeleton!(insert_expr, position: Position, base: Box<dyn Expression>, insertion_text: Box<dyn Expression>);
+define_opexp_skeleton!(overwrite_expr, input: Box<dyn Expression>, index: usize, text: Box<dyn Expression>);
 define_opexp_skeleton!(replace_expr, content: Box<dyn Expression>, selection: Selection, find: Box<dyn Expression>, replacement: Box<dyn Expression>);
-define_opexp_skeleton!(if_expr, condition: MatchRule, then_expr: Box<dyn Expression>, else_expr: Option<Box<dyn Expression>>);
 define_opexp_skeleton!(convert_case_expr, case: Case, input: Box<dyn Expression>);
+define_opexp_skeleton!(convert_stem_case_expr, case: Case, input: Box<dyn Expression>);
+define_opexp_skeleton!(title_case_expr, input: Box<dyn Expression>, small_words: Vec<String>);
+define_opexp_skeleton!(ascii_fold_expr, input: Box<dyn Expression>, substitute: String);
+define_opexp_skeleton!(normalize_separators_expr, input: Box<dyn Expression>, separators: Vec<char>, target: char);
+define_opexp_skeleton!(cleanup_expr, input: Box<dyn Expression>);
 define_opexp_skeleton!(to_upper_case_expr, input: Box<dyn Expression>);
 define_opexp_skeleton!(to_lower_case_expr, input: Box<dyn Expression>);
 define_opexp_skeleton!(variable_expr, var: String);
@@ -23,11 +54,22 @@ define_opexp_skeleton!(assign_variable_expr, var: String, value: Box<dyn Express
 define_opexp_skeleton!(left_expr, input: Box<dyn Expression>, match_str: Box<dyn Expression>, inclusive: bool);
 define_opexp_skeleton!(right_expr, input: Box<dyn Expression>, match_str: Box<dyn Expression>, inclusive: bool);
 define_opexp_skeleton!(add_expr, lhs: Box<dyn Expression>, rhs: Box<dyn Expression>);
-define_opexp_skeleton!(combine_expr, exprs: Vec<Box<dyn Expression>>);
+define_opexp_skeleton!(concat_expr, parts: Vec<Box<dyn Expression>>, separator: Option<String>, skip_none: bool);
+define_opexp_skeleton!(repeat_expr, input: Box<dyn Expression>, count: usize, separator: Option<String>);
 define_opexp_skeleton!(constant_expr, value: String);
 define_opexp_skeleton!(file_name_expr);
 define_opexp_skeleton!(file_stem_expr);
 define_opexp_skeleton!(file_extension_expr);
+define_opexp_skeleton!(file_meta_expr, key: String);
+define_opexp_skeleton!(parent_dir_name_expr, levels_up: usize);
+define_opexp_skeleton!(lookup_expr, key: Box<dyn Expression>, table: HashMap<String, String>, default: Option<Box<dyn Expression>>);
+define_opexp_skeleton!(sibling_exists_expr, stem_same: bool, extension: Box<dyn Expression>);
+define_opexp_skeleton!(path_hash_expr, length: usize);
+define_opexp_skeleton!(root_relative_path_expr, separator: Option<String>);
+define_opexp_skeleton!(depth_expr);
+define_opexp_skeleton!(sibling_name_expr, sibling: Box<dyn Expression>);
+define_opexp_skeleton!(source_path_expr, relative_to: Option<PathBuf>, separator: Option<String>);
+define_opexp_skeleton!(flatten_path_expr, separator: String, relative_to: PathBuf);
 
 macro_rules! unwrap_res_op {
     ($e:expr) => {{
@@ -39,26 +81,169 @@ macro_rules! unwrap_res_op {
     }};
 }
 
+fn char_to_byte_index(s: &str, index: usize) -> usize {
+    return s
+        .char_indices()
+        .nth(index)
+        .map(|(i, _)| i)
+        .unwrap_or(s.len());
+}
+
+fn fold_to_ascii(c: char) -> Option<&'static str> {
+    return Some(match c {
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'Ð' | 'Ď' | 'Đ' => "D",
+        'ð' | 'ď' | 'đ' => "d",
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'Ĥ' | 'Ħ' => "H",
+        'ĥ' | 'ħ' => "h",
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'Ĵ' => "J",
+        'ĵ' => "j",
+        'Ķ' => "K",
+        'ķ' => "k",
+        'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' => "N",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ŕ' | 'Ŗ' | 'Ř' => "R",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'Ś' | 'Ŝ' | 'Ş' | 'Š' => "S",
+        'ś' | 'ŝ' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'Ţ' | 'Ť' | 'Ŧ' => "T",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'Þ' => "TH",
+        'þ' => "th",
+        'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'Ŵ' => "W",
+        'ŵ' => "w",
+        'Ý' | 'Ÿ' | 'Ŷ' => "Y",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    });
+}
+
+/// Byte ranges of the non-overlapping matches of `marker` in `base`, compared
+/// by lowercased characters when `ignore_case` is set.
+fn match_ranges(base: &str, marker: &str, ignore_case: bool) -> Vec<(usize, usize)> {
+    if !ignore_case || marker.is_empty() {
+        return base
+            .match_indices(marker)
+            .map(|(i, m)| (i, i + m.len()))
+            .collect();
+    }
+
+    let marker: Vec<char> = marker.chars().flat_map(char::to_lowercase).collect();
+    let mut ranges = Vec::new();
+    let mut from = 0;
+
+    'start: for (start, _) in base.char_indices() {
+        if start < from {
+            continue;
+        }
+
+        let mut rest = marker.as_slice();
+
+        for (i, c) in base[start..].char_indices() {
+            for lower in c.to_lowercase() {
+                match rest.split_first() {
+                    Some((m, tail)) if *m == lower => rest = tail,
+                    _ => continue 'start,
+                }
+            }
+
+            if rest.is_empty() {
+                from = start + i + c.len_utf8();
+                ranges.push((start, from));
+
+                continue 'start;
+            }
+        }
+    }
+
+    return ranges;
+}
+
+fn insert_at_all(
+    base: &str,
+    marker: &str,
+    insertion_text: &str,
+    after: bool,
+    ignore_case: bool,
+) -> String {
+    if marker.is_empty() {
+        return base.to_string();
+    }
+
+    let mut output = String::with_capacity(base.len());
+    let mut last = 0;
+
+    for (start, end) in match_ranges(base, marker, ignore_case) {
+        let at = if after { end } else { start };
+
+        output.push_str(&base[last..at]);
+        output.push_str(insertion_text);
+        last = at;
+    }
+
+    output.push_str(&base[last..]);
+
+    return output;
+}
+
 impl Expression for InsertExpr {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
         let mut base = unwrap_res_op!(self.base.execute(engine));
         let insertion_text = unwrap_res_op!(self.insertion_text.execute(engine));
+        let ignore_case = engine.case_insensitive();
 
         return Ok(Some(match &self.position {
             Position::Index(i) => {
-                base.insert_str(*i.min(&base.len()), &insertion_text);
+                base.insert_str(char_to_byte_index(&base, *i), &insertion_text);
+
+                base
+            }
+            Position::IndexFromEnd(i) => {
+                let index = base.chars().count().saturating_sub(*i);
+
+                base.insert_str(char_to_byte_index(&base, index), &insertion_text);
 
                 base
             }
+            Position::EveryN(0) => return Err(Error::ZeroInsertInterval),
+            Position::EveryN(n) => base
+                .chars()
+                .collect::<Vec<char>>()
+                .chunks(*n)
+                .map(|c| c.iter().collect::<String>())
+                .join(&insertion_text),
             Position::After(f) => {
-                let Some(insert_pos) = base.find(f) else {
+                let Some(&(_, insert_pos)) = match_ranges(&base, f, ignore_case).first() else {
                     return Ok(None);
                 };
 
-                base.insert_str(insert_pos + f.len(), &insertion_text);
+                base.insert_str(insert_pos, &insertion_text);
 
                 base
             }
+            Position::AfterAll(f) => insert_at_all(&base, f, &insertion_text, true, ignore_case),
             #[cfg(feature = "regex_match")]
             Position::AfterRegex(r) => {
                 let Some(insert_pos) = r.find(&base) else {
@@ -70,7 +255,7 @@ impl Expression for InsertExpr {
                 base
             }
             Position::Before(f) => {
-                let Some(insert_pos) = base.find(f) else {
+                let Some(&(insert_pos, _)) = match_ranges(&base, f, ignore_case).first() else {
                     return Ok(None);
                 };
 
@@ -78,6 +263,7 @@ impl Expression for InsertExpr {
 
                 base
             }
+            Position::BeforeAll(f) => insert_at_all(&base, f, &insertion_text, false, ignore_case),
             #[cfg(feature = "regex_match")]
             Position::BeforeRegex(r) => {
                 let Some(insert_pos) = r.find(&base) else {
@@ -98,11 +284,69 @@ impl Expression for InsertExpr {
     clone_dyn!(Expression);
 }
 
+impl Expression for OverwriteExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let input = unwrap_res_op!(self.input.execute(engine));
+        let text = unwrap_res_op!(self.text.execute(engine));
+
+        let start = char_to_byte_index(&input, self.index);
+        let end = char_to_byte_index(&input, self.index + text.chars().count());
+
+        let mut output = input;
+        output.replace_range(start..end, &text);
+
+        return Ok(Some(output));
+    }
+
+    clone_dyn!(Expression);
+}
+
+/// Resolves `condition` against the current file name, or against the
+/// expression set with [`IfExpr::with_subject`].
+#[derive(Debug, Clone)]
+pub struct IfExpr {
+    condition: MatchRule,
+    then_expr: Box<dyn Expression>,
+    else_expr: Option<Box<dyn Expression>>,
+    subject: Option<Box<dyn Expression>>,
+}
+
+impl IfExpr {
+    pub fn new(
+        condition: MatchRule,
+        then_expr: Box<dyn Expression>,
+        else_expr: Option<Box<dyn Expression>>,
+    ) -> Self {
+        return Self {
+            condition,
+            then_expr,
+            else_expr,
+            subject: None,
+        };
+    }
+
+    pub fn with_subject(mut self, subject: Box<dyn Expression>) -> Self {
+        self.subject = Some(subject);
+
+        return self;
+    }
+}
+
 impl Expression for IfExpr {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
-        let cond = self
-            .condition
-            .resolve(&FileNameExpr::new().execute(engine)?.unwrap());
+        let cond = match &self.subject {
+            Some(subject) => subject
+                .execute(engine)?
+                .map(|s| engine.resolve(&self.condition, &s))
+                .unwrap_or(false),
+            None => {
+                let name = FileNameExpr::new()
+                    .execute(engine)?
+                    .ok_or(Error::CannotIdentifyFileName)?;
+
+                engine.resolve(&self.condition, &name)
+            }
+        };
 
         if cond {
             return self.then_expr.execute(engine);
@@ -128,6 +372,234 @@ impl Expression for RegexMatchExpr {
     clone_dyn!(Expression);
 }
 
+// Recognises "S01E02", "s1.e2" and "1x02" style markers. The output format may
+// use `{season}` and `{episode}`, optionally zero padded as `{season:2}`.
+#[cfg(feature = "regex_match")]
+impl Expression for SeasonEpisodeExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        static PATTERNS: LazyLock<[Regex; 2]> = LazyLock::new(|| {
+            return [
+                Regex::new(r"(?i)\bs(\d{1,2})[ ._-]?e(\d{1,3})\b").unwrap(),
+                Regex::new(r"(?i)\b(\d{1,2})x(\d{1,3})\b").unwrap(),
+            ];
+        });
+        static PLACEHOLDER: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"\{(season|episode)(?::(\d+))?\}").unwrap());
+
+        let input = unwrap_res_op!(self.input.execute(engine));
+
+        let Some((season, episode)) = PATTERNS.iter().find_map(|p| {
+            let captures = p.captures(&input)?;
+
+            return Some((
+                captures[1].parse::<u32>().ok()?,
+                captures[2].parse::<u32>().ok()?,
+            ));
+        }) else {
+            return Ok(None);
+        };
+
+        let output = PLACEHOLDER.replace_all(&self.output_format, |c: &regex::Captures| {
+            let value = if &c[1] == "season" { season } else { episode };
+            let width = c.get(2).and_then(|w| w.as_str().parse().ok()).unwrap_or(0);
+
+            return format!("{:0width$}", value, width = width);
+        });
+
+        return Ok(Some(output.into_owned()));
+    }
+
+    clone_dyn!(Expression);
+}
+
+// Recognises "01 - Song", "1. Song", "01_Song" and "1 Song". `Pad` rewrites the
+// number as "01 Song", `Reformat` replaces the number and its separator with
+// the template, which may use `{track}` or a padded `{track:2}`. Only the stem
+// is matched, so the dot in "01.mp3" is not taken as a separator.
+#[cfg(feature = "regex_match")]
+impl Expression for TrackNumberExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        static PATTERN: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^(\d{1,3})(?:\s*[-._)]\s*|\s+)(\D.*)$").unwrap());
+        static PLACEHOLDER: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"\{track(?::(\d+))?\}").unwrap());
+
+        let input = unwrap_res_op!(self.input.execute(engine));
+
+        let (stem, extension) = match input.rfind('.') {
+            Some(i) if i > 0 && !input[i + 1..].is_empty() && !input[i + 1..].contains(' ') => {
+                input.split_at(i)
+            }
+            _ => (input.as_str(), ""),
+        };
+
+        let Some(captures) = PATTERN.captures(stem) else {
+            return Ok(Some(input));
+        };
+
+        let track: u32 = captures[1].parse().unwrap();
+        let rest = format!("{}{}", &captures[2], extension);
+
+        let output = match &self.mode {
+            TrackNumberMode::Strip => rest.to_string(),
+            TrackNumberMode::Pad(width) => format!("{:0width$} {}", track, rest, width = width),
+            TrackNumberMode::Reformat(template) => {
+                let prefix = PLACEHOLDER.replace_all(template, |c: &regex::Captures| {
+                    let width = c.get(1).and_then(|w| w.as_str().parse().ok()).unwrap_or(0);
+
+                    return format!("{:0width$}", track, width = width);
+                });
+
+                format!("{}{}", prefix, rest)
+            }
+        };
+
+        return Ok(Some(output));
+    }
+
+    clone_dyn!(Expression);
+}
+
+#[cfg(feature = "datetime")]
+impl Expression for ParseDateExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        use std::fmt::Write;
+
+        let input = unwrap_res_op!(self.input.execute(engine));
+
+        // Formats without a time component only parse as a date, so fall back to midnight.
+        let parsed = match NaiveDateTime::parse_from_str(&input, &self.input_format) {
+            Ok(dt) => dt,
+            Err(_) => match NaiveDate::parse_from_str(&input, &self.input_format) {
+                Ok(d) => d.and_hms_opt(0, 0, 0).unwrap(),
+                Err(_) => return Ok(None),
+            },
+        };
+
+        let mut output = String::new();
+
+        if write!(output, "{}", parsed.format(&self.output_format)).is_err() {
+            return Ok(None);
+        }
+
+        return Ok(Some(output));
+    }
+
+    clone_dyn!(Expression);
+}
+
+// Formats the time the run started rather than the current time so that every
+// file in a batch gets the same stamp.
+#[cfg(feature = "datetime")]
+impl Expression for NowExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        use std::fmt::Write;
+
+        let started = engine.started();
+        let mut output = String::new();
+
+        let res = match self.utc {
+            true => write!(
+                output,
+                "{}",
+                DateTime::<Utc>::from(started).format(&self.format)
+            ),
+            false => write!(
+                output,
+                "{}",
+                DateTime::<Local>::from(started).format(&self.format)
+            ),
+        };
+
+        if res.is_err() {
+            return Ok(None);
+        }
+
+        return Ok(Some(output));
+    }
+
+    clone_dyn!(Expression);
+}
+
+// Values that are not valid UTF-8 are treated like a missing attribute. A
+// trailing NUL, as written by some tagging tools, is dropped.
+#[cfg(feature = "xattr")]
+impl Expression for XattrExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let value = xattr::get(&engine.current_file().source, &self.name)
+            .map_err(|e| Error::ReadFileError(e))?;
+
+        return Ok(value
+            .and_then(|v| String::from_utf8(v).ok())
+            .map(|v| v.trim_end_matches('\0').to_string()));
+    }
+
+    clone_dyn!(Expression);
+}
+
+#[cfg(feature = "exif")]
+impl Expression for ExifDateExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        use std::fmt::Write;
+
+        let file =
+            fs::File::open(&engine.current_file().source).map_err(|e| Error::ReadFileError(e))?;
+
+        let Ok(exif) = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(file))
+        else {
+            return Ok(None);
+        };
+
+        let Some(field) = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY) else {
+            return Ok(None);
+        };
+
+        let exif::Value::Ascii(values) = &field.value else {
+            return Ok(None);
+        };
+
+        let Some(raw) = values.first().and_then(|v| std::str::from_utf8(v).ok()) else {
+            return Ok(None);
+        };
+
+        let Ok(parsed) = NaiveDateTime::parse_from_str(raw.trim(), "%Y:%m:%d %H:%M:%S") else {
+            return Ok(None);
+        };
+
+        let mut output = String::new();
+
+        if write!(output, "{}", parsed.format(&self.format)).is_err() {
+            return Ok(None);
+        }
+
+        return Ok(Some(output));
+    }
+
+    clone_dyn!(Expression);
+}
+
+#[cfg(feature = "regex_match")]
+impl Expression for CaptureToVariableExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let input = unwrap_res_op!(self.input.execute(engine));
+
+        let Some(captured) = self
+            .regex
+            .captures(&input)
+            .and_then(|c| c.get(self.group))
+            .map(|m| m.as_str().to_string())
+        else {
+            return Ok(None);
+        };
+
+        engine.set_variable(self.var.clone(), captured.clone());
+
+        return Ok(Some(captured));
+    }
+
+    clone_dyn!(Expression);
+}
+
 impl Expression for ConvertCaseExpr {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
         return Ok(self.input.execute(engine)?.map(|v| v.to_case(self.case)));
@@ -136,6 +608,125 @@ impl Expression for ConvertCaseExpr {
     clone_dyn!(Expression);
 }
 
+impl Expression for ConvertStemCaseExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let input = unwrap_res_op!(self.input.execute(engine));
+        let path = Path::new(&input);
+
+        let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+            return Ok(Some(input.to_case(self.case)));
+        };
+
+        return Ok(Some(match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) => format!("{}.{}", stem.to_case(self.case), extension),
+            None => stem.to_case(self.case),
+        }));
+    }
+
+    clone_dyn!(Expression);
+}
+
+impl Expression for TitleCaseExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let input = unwrap_res_op!(self.input.execute(engine));
+
+        return Ok(Some(
+            input
+                .split(' ')
+                .enumerate()
+                .map(|(i, word)| {
+                    let is_acronym = word.chars().count() > 1
+                        && word.chars().all(|c| !c.is_alphabetic() || c.is_uppercase());
+
+                    if is_acronym {
+                        return word.to_string();
+                    }
+
+                    let lower = word.to_lowercase();
+
+                    if i > 0 && self.small_words.iter().any(|w| w.to_lowercase() == lower) {
+                        return lower;
+                    }
+
+                    let mut chars = lower.chars();
+
+                    return match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect(),
+                        None => lower,
+                    };
+                })
+                .join(" "),
+        ));
+    }
+
+    clone_dyn!(Expression);
+}
+
+impl Expression for AsciiFoldExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let input = unwrap_res_op!(self.input.execute(engine));
+        let mut output = String::with_capacity(input.len());
+
+        for c in input.chars() {
+            if c.is_ascii() {
+                output.push(c);
+            } else if let Some(folded) = fold_to_ascii(c) {
+                output.push_str(folded);
+            } else {
+                output.push_str(&self.substitute);
+            }
+        }
+
+        return Ok(Some(output));
+    }
+
+    clone_dyn!(Expression);
+}
+
+impl Expression for NormalizeSeparatorsExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let input = unwrap_res_op!(self.input.execute(engine));
+        let mut output = String::with_capacity(input.len());
+
+        for c in input.chars() {
+            let c = if self.separators.contains(&c) {
+                self.target
+            } else {
+                c
+            };
+
+            if c == self.target && output.ends_with(self.target) {
+                continue;
+            }
+
+            output.push(c);
+        }
+
+        return Ok(Some(output));
+    }
+
+    clone_dyn!(Expression);
+}
+
+// Control characters other than whitespace are dropped, runs of whitespace
+// (tabs and newlines included) become a single space and the ends are trimmed.
+impl Expression for CleanupExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let input = unwrap_res_op!(self.input.execute(engine));
+
+        return Ok(Some(
+            input
+                .chars()
+                .filter(|c| !c.is_control() || c.is_whitespace())
+                .collect::<String>()
+                .split_whitespace()
+                .join(" "),
+        ));
+    }
+
+    clone_dyn!(Expression);
+}
+
 impl Expression for ToUpperCaseExpr {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
         return Ok(self.input.execute(engine)?.map(|v| v.to_uppercase()));
@@ -245,8 +836,66 @@ impl Expression for AddExpr {
     clone_dyn!(Expression);
 }
 
+impl Expression for ConcatExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let mut values = Vec::with_capacity(self.parts.len());
+
+        for part in &self.parts {
+            match part.execute(engine)? {
+                Some(v) => values.push(v),
+                None if !self.skip_none => values.push(String::new()),
+                None => {}
+            }
+        }
+
+        if values.is_empty() {
+            return Ok(None);
+        }
+
+        return Ok(Some(
+            values.join(self.separator.as_deref().unwrap_or_default()),
+        ));
+    }
+
+    clone_dyn!(Expression);
+}
+
+/// Joins the results of `exprs`, skipping those that give no value unless
+/// [`CombineExpr::strict`] is set.
+#[derive(Debug, Clone)]
+pub struct CombineExpr {
+    exprs: Vec<Box<dyn Expression>>,
+    strict: bool,
+}
+
+impl CombineExpr {
+    pub fn new(exprs: Vec<Box<dyn Expression>>) -> Self {
+        return Self {
+            exprs,
+            strict: false,
+        };
+    }
+
+    /// Gives no value at all if any expression gives none.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+
+        return self;
+    }
+}
+
 impl Expression for CombineExpr {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        if self.strict {
+            let mut working = String::new();
+
+            for e in &self.exprs {
+                working.push_str(&unwrap_res_op!(e.execute(engine)));
+            }
+
+            return Ok(Some(working));
+        }
+
         let working = self
             .exprs
             .iter()
@@ -254,7 +903,7 @@ impl Expression for CombineExpr {
             .filter_map_ok(|o| o)
             .fold_ok(String::new(), |a, b| format!("{}{}", a, b))?;
 
-        if working == "" {
+        if working.is_empty() {
             return Ok(None);
         }
 
@@ -264,12 +913,28 @@ impl Expression for CombineExpr {
     clone_dyn!(Expression);
 }
 
+impl Expression for RepeatExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let input = unwrap_res_op!(self.input.execute(engine));
+
+        return Ok(Some(
+            std::iter::repeat_n(input, self.count).join(self.separator.as_deref().unwrap_or("")),
+        ));
+    }
+
+    clone_dyn!(Expression);
+}
+
 impl Expression for ConstantExpr {
     fn execute(&self, _engine: &mut OperationEngine) -> Result<Option<String>, Error> {
         return Ok(Some(self.value.clone()));
     }
 
     clone_dyn!(Expression);
+
+    fn is_constant(&self) -> bool {
+        return true;
+    }
 }
 
 impl<'a> From<&'a str> for ConstantExpr {
@@ -298,12 +963,7 @@ impl<'a> From<&'a str> for Box<dyn Expression> {
 
 impl Expression for FileNameExpr {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
-        return Ok(engine
-            .current_file()
-            .destination
-            .file_name()
-            .and_then(|s| s.to_str())
-            .map(|s| s.to_string()));
+        return Ok(engine.current_name());
     }
 
     clone_dyn!(Expression);
@@ -311,36 +971,332 @@ impl Expression for FileNameExpr {
 
 impl Expression for FileStemExpr {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
-        return Ok(engine
-            .current_file()
-            .destination
-            .file_stem()
-            .and_then(|s| s.to_str())
+        let Some(name) = engine.current_name() else {
+            return Ok(None);
+        };
+
+        return Ok(Path::new(&name)
+            .with_extension("")
+            .to_str()
             .map(|s| s.to_string()));
     }
 
     clone_dyn!(Expression);
 }
 
-impl Expression for FileExtensionExpr {
+impl Expression for FileMetaExpr {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
-        return Ok(engine
-            .current_file()
-            .destination
-            .extension()
-            .and_then(|s| s.to_str())
-            .map(|s| s.to_string()));
+        return Ok(engine.current_file().metadata.get(&self.key).cloned());
     }
 
     clone_dyn!(Expression);
 }
 
-impl Expression for ReplaceExpr {
+impl Expression for FileExtensionExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let Some(name) = engine.current_name() else {
+            return Ok(None);
+        };
+
+        return Ok(Path::new(&name)
+            .extension()
+            .and_then(|s| s.to_str())
+            .map(|s| s.to_string()));
+    }
+
+    clone_dyn!(Expression);
+}
+
+impl Expression for ParentDirNameExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        return Ok(engine
+            .current_file()
+            .source
+            .ancestors()
+            .nth(self.levels_up)
+            .and_then(|p| p.file_name())
+            .and_then(|s| s.to_str())
+            .map(|s| s.to_string()));
+    }
+
+    clone_dyn!(Expression);
+}
+
+impl LookupExpr {
+    /// Loads the table from a two column `key,value` CSV file. Blank lines are
+    /// skipped and values may contain further commas.
+    pub fn from_csv<P: AsRef<Path>>(
+        key: Box<dyn Expression>,
+        path: P,
+        default: Option<Box<dyn Expression>>,
+    ) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::ReadFileError(e))?;
+        let mut table = HashMap::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let Some((k, v)) = line.split_once(',') else {
+                return Err(Error::InvalidLookupTable(format!(
+                    "line {} has no ',' separator",
+                    i + 1
+                )));
+            };
+
+            table.insert(k.trim().to_string(), v.trim().to_string());
+        }
+
+        return Ok(Self::new(key, table, default));
+    }
+}
+
+impl Expression for LookupExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let key = unwrap_res_op!(self.key.execute(engine));
+
+        if let Some(value) = self.table.get(&key) {
+            return Ok(Some(value.clone()));
+        }
+
+        return match &self.default {
+            Some(default) => default.execute(engine),
+            None => Ok(None),
+        };
+    }
+
+    clone_dyn!(Expression);
+}
+
+impl Expression for SiblingExistsExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let extension = unwrap_res_op!(self.extension.execute(engine));
+        let extension = extension.trim_start_matches('.');
+
+        let source = engine.current_file().source.clone();
+        let parent = match source.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+
+        let exists = if self.stem_same {
+            let stem = source
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or(Error::CannotIdentifyFileName)?;
+            let sibling = parent.join(format!("{}.{}", stem, extension));
+
+            sibling.is_file() && sibling.file_name() != source.file_name()
+        } else {
+            let mut found = false;
+
+            for entry in fs::read_dir(parent).map_err(|e| Error::ReadDirError(e))? {
+                let path = entry.map_err(|e| Error::ReadDirEntryError(e))?.path();
+
+                if path.is_file()
+                    && path.file_name() != source.file_name()
+                    && path.extension().and_then(|e| e.to_str()) == Some(extension)
+                {
+                    found = true;
+                    break;
+                }
+            }
+
+            found
+        };
+
+        return Ok(Some(exists.to_string()));
+    }
+
+    clone_dyn!(Expression);
+}
+
+// The source path below the root of the `Dir` the file came from, so builds
+// with several roots each get paths relative to their own root.
+impl Expression for RootRelativePathExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let file = engine.current_file();
+
+        let Some(relative) = file
+            .root
+            .as_ref()
+            .and_then(|root| file.source.strip_prefix(root).ok())
+        else {
+            return Ok(None);
+        };
+
+        let Some(relative) = relative.to_str() else {
+            return Ok(None);
+        };
+
+        return Ok(Some(match &self.separator {
+            Some(sep) => relative.replace(MAIN_SEPARATOR, sep),
+            None => relative.to_string(),
+        }));
+    }
+
+    clone_dyn!(Expression);
+}
+
+impl Expression for DepthExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        return Ok(Some(engine.current_file().depth.to_string()));
+    }
+
+    clone_dyn!(Expression);
+}
+
+// `sibling` names a file next to the current one by its original name. Only
+// has a value in the second pass of `RenameTree::run_two_pass`.
+impl Expression for SiblingNameExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let sibling = unwrap_res_op!(self.sibling.execute(engine));
+        let source = engine.current_file().source.with_file_name(sibling);
+
+        return Ok(engine
+            .first_pass_destination(&source)
+            .and_then(|d| d.file_name())
+            .and_then(|n| n.to_str())
+            .map(|n| n.to_string()));
+    }
+
+    clone_dyn!(Expression);
+}
+
+// FNV-1a is used rather than `DefaultHasher` because its output is
+// guaranteed not to change between Rust releases.
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    return hash;
+}
+
+fn to_base36(mut value: u64) -> String {
+    const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    // u64::MAX has 13 base36 digits.
+    let mut digits = [b'0'; 13];
+
+    for d in digits.iter_mut().rev() {
+        *d = DIGITS[(value % 36) as usize];
+        value /= 36;
+    }
+
+    return digits.iter().map(|d| *d as char).collect();
+}
+
+impl Expression for PathHashExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let source = engine.current_file().source.as_os_str().as_encoded_bytes();
+        let mut id = to_base36(fnv1a_64(source));
+
+        id.truncate(self.length);
+
+        return Ok(Some(id));
+    }
+
+    clone_dyn!(Expression);
+}
+
+impl Expression for SourcePathExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let source = &engine.current_file().source;
+
+        let path = match &self.relative_to {
+            Some(base) => match source.strip_prefix(base) {
+                Ok(p) => p,
+                Err(_) => return Ok(None),
+            },
+            None => source.as_path(),
+        };
+
+        let Some(path) = path.to_str() else {
+            return Ok(None);
+        };
+
+        return Ok(Some(match &self.separator {
+            Some(sep) => path.replace(MAIN_SEPARATOR, sep),
+            None => path.to_string(),
+        }));
+    }
+
+    clone_dyn!(Expression);
+}
+
+// Joins the components of the source path below `relative_to` with
+// `separator`, so "a/b/c.txt" becomes "a_b_c.txt". The base is compared
+// canonicalized as well since tree sources are canonical paths.
+impl Expression for FlattenPathExpr {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
+        let source = &engine.current_file().source;
+
+        let relative = match source.strip_prefix(&self.relative_to) {
+            Ok(p) => p,
+            Err(_) => match self
+                .relative_to
+                .canonicalize()
+                .ok()
+                .and_then(|base| source.strip_prefix(base).ok())
+            {
+                Some(p) => p,
+                None => return Ok(None),
+            },
+        };
+
+        let mut parts = Vec::new();
+
+        for component in relative.components() {
+            let Some(part) = component.as_os_str().to_str() else {
+                return Ok(None);
+            };
+
+            parts.push(part);
+        }
+
+        if parts.is_empty() {
+            return Ok(None);
+        }
+
+        return Ok(Some(parts.join(&self.separator)));
+    }
+
+    clone_dyn!(Expression);
+}
+
+impl Expression for ReplaceExpr {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error> {
         let input = unwrap_res_op!(self.content.execute(engine));
         let matches = unwrap_res_op!(self.find.execute(engine));
         let replacement = unwrap_res_op!(self.replacement.execute(engine));
 
+        if engine.case_insensitive() && !matches.is_empty() {
+            let ranges = match_ranges(&input, &matches, true);
+            let ranges = match self.selection {
+                Selection::First => &ranges[..ranges.len().min(1)],
+                Selection::Last => &ranges[ranges.len().saturating_sub(1)..],
+                Selection::All => &ranges[..],
+            };
+
+            let mut output = String::with_capacity(input.len());
+            let mut last = 0;
+
+            for (start, end) in ranges {
+                output.push_str(&input[last..*start]);
+                output.push_str(&replacement);
+                last = *end;
+            }
+
+            output.push_str(&input[last..]);
+
+            return Ok(Some(output));
+        }
+
         return match self.selection {
             Selection::First => {
                 // Could be better optimized
@@ -383,106 +1339,894 @@ impl Expression for ReplaceExpr {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::operations::file::{SetNameOperation, SetStemOperation};
+    use crate::File;
+
+    fn engine_with_file(path: &str) -> OperationEngine {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+        engine.process_file(File::new(path)).unwrap();
+
+        return engine;
+    }
 
     #[test]
-    fn test_combine_1() {
-        let r = CombineExpr::new(vec![
-            "test".into(),
-            " ".into(),
-            "yo".into(),
-            " ".into(),
-            "hello".into(),
-        ])
-        .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
-        .unwrap()
-        .unwrap();
+    fn test_match_ranges_1() {
+        assert_eq!(match_ranges("aXa xa", "xa", true), vec![(1, 3), (4, 6)]);
+        assert_eq!(match_ranges("aXa xa", "xa", false), vec![(4, 6)]);
+        assert_eq!(match_ranges("ÉTÉ été", "été", true), vec![(0, 5), (6, 11)]);
+    }
 
-        assert_eq!(r, "test yo hello");
+    #[test]
+    fn test_insert_case_insensitive_1() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new()).with_case_insensitive(true);
+
+        assert_eq!(
+            InsertExpr::new(
+                Position::Before("LIVE".to_string()),
+                "Song - live.mp3".into(),
+                "(".into()
+            )
+            .execute(&mut engine)
+            .unwrap()
+            .unwrap(),
+            "Song - (live.mp3"
+        );
     }
 
     #[test]
-    fn test_combine_2() {
-        let r = CombineExpr::new(vec![
-            "test".into(),
-            " ".into(),
-            ReplaceExpr::new(
-                "test message hello".into(),
-                Selection::Last,
-                "message".into(),
-                "yo".into(),
+    fn test_normalize_separators_1() {
+        assert_eq!(
+            NormalizeSeparatorsExpr::new("my-file_name.txt".into(), vec!['-', '_', ' '], '-')
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "my-file-name.txt"
+        );
+    }
+
+    #[test]
+    fn test_normalize_separators_2() {
+        assert_eq!(
+            NormalizeSeparatorsExpr::new(
+                "Some  Band - Track_01 _ Live".into(),
+                vec!['-', '_', ' '],
+                '-'
             )
-            .into(),
-            " ".into(),
-            "hello".into(),
-        ])
-        .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
-        .unwrap()
-        .unwrap();
+            .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+            .unwrap()
+            .unwrap(),
+            "Some-Band-Track-01-Live"
+        );
+    }
+
+    #[test]
+    fn test_normalize_separators_3() {
+        assert_eq!(
+            NormalizeSeparatorsExpr::new("a--b__c".into(), vec!['_'], '.')
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "a--b.c"
+        );
+    }
+
+    #[test]
+    fn test_cleanup_1() {
+        assert_eq!(
+            CleanupExpr::new("  My\tHoliday  \t Photo .jpg ".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "My Holiday Photo .jpg"
+        );
+    }
+
+    #[test]
+    fn test_cleanup_2() {
+        assert_eq!(
+            CleanupExpr::new("Track\u{0}01\u{7}\r\n- Intro.mp3".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "Track01 - Intro.mp3"
+        );
+    }
+
+    #[test]
+    fn test_cleanup_3() {
+        assert_eq!(
+            CleanupExpr::new(FileMetaExpr::new("title".to_string()).into())
+                .execute(&mut engine_with_file("a.txt"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ascii_fold_1() {
+        assert_eq!(
+            AsciiFoldExpr::new("Mötley Crüe - Élan à l'été".into(), String::new())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "Motley Crue - Elan a l'ete"
+        );
+    }
+
+    #[test]
+    fn test_ascii_fold_2() {
+        assert_eq!(
+            AsciiFoldExpr::new("Straße".into(), String::new())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "Strasse"
+        );
+    }
+
+    #[test]
+    fn test_ascii_fold_3() {
+        assert_eq!(
+            AsciiFoldExpr::new("party 🎉.jpg".into(), "_".to_string())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "party _.jpg"
+        );
+        assert_eq!(
+            AsciiFoldExpr::new("party 🎉.jpg".into(), String::new())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "party .jpg"
+        );
+    }
+
+    #[test]
+    fn test_convert_stem_case_1() {
+        assert_eq!(
+            ConvertStemCaseExpr::new(Case::Kebab, "My File.TXT".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "my-file.TXT"
+        );
+    }
+
+    #[test]
+    fn test_convert_stem_case_2() {
+        assert_eq!(
+            ConvertStemCaseExpr::new(Case::Kebab, "My File".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "my-file"
+        );
+    }
+
+    #[test]
+    fn test_concat_1() {
+        assert_eq!(
+            ConcatExpr::new(
+                vec!["2024".into(), "05".into(), "holiday".into()],
+                Some("_".to_string()),
+                true
+            )
+            .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+            .unwrap()
+            .unwrap(),
+            "2024_05_holiday"
+        );
+    }
+
+    #[test]
+    fn test_concat_2() {
+        let parts: Vec<Box<dyn Expression>> = vec![
+            "a".into(),
+            IfExpr::new(MatchRule::Equals("x".to_string()), "x".into(), None)
+                .with_subject("y".into())
+                .into(),
+            "b".into(),
+        ];
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        assert_eq!(
+            ConcatExpr::new(parts.clone(), Some("-".to_string()), true)
+                .execute(&mut engine)
+                .unwrap()
+                .unwrap(),
+            "a-b"
+        );
+        assert_eq!(
+            ConcatExpr::new(parts, Some("-".to_string()), false)
+                .execute(&mut engine)
+                .unwrap()
+                .unwrap(),
+            "a--b"
+        );
+    }
+
+    #[test]
+    fn test_concat_3() {
+        assert_eq!(
+            ConcatExpr::new(Vec::new(), Some("-".to_string()), true)
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_combine_1() {
+        let r = CombineExpr::new(vec![
+            "test".into(),
+            " ".into(),
+            "yo".into(),
+            " ".into(),
+            "hello".into(),
+        ])
+        .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(r, "test yo hello");
+    }
+
+    #[test]
+    fn test_combine_2() {
+        let r = CombineExpr::new(vec![
+            "test".into(),
+            " ".into(),
+            ReplaceExpr::new(
+                "test message hello".into(),
+                Selection::Last,
+                "message".into(),
+                "yo".into(),
+            )
+            .into(),
+            " ".into(),
+            "hello".into(),
+        ])
+        .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(r, "test test yo hello hello");
+    }
+
+    #[test]
+    fn test_repeat_1() {
+        assert_eq!(
+            RepeatExpr::new("ab".into(), 3, None)
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "ababab"
+        );
+    }
+
+    #[test]
+    fn test_repeat_2() {
+        assert_eq!(
+            RepeatExpr::new("ab".into(), 3, Some("-".to_string()))
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "ab-ab-ab"
+        );
+    }
+
+    #[test]
+    fn test_repeat_3() {
+        assert_eq!(
+            RepeatExpr::new("ab".into(), 0, Some("-".to_string()))
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_repeat_4() {
+        assert_eq!(
+            RepeatExpr::new(FileExtensionExpr::new().into(), 3, None)
+                .execute(&mut engine_with_file("notes"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_combine_strict_1() {
+        assert_eq!(
+            CombineExpr::new(vec!["test".into(), FileExtensionExpr::new().into()])
+                .strict()
+                .execute(&mut engine_with_file("notes"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_combine_lenient_1() {
+        assert_eq!(
+            CombineExpr::new(vec!["test".into(), FileExtensionExpr::new().into()])
+                .execute(&mut engine_with_file("notes"))
+                .unwrap()
+                .unwrap(),
+            "test"
+        );
+    }
+
+    #[test]
+    fn test_replace_first_1() {
+        assert_eq!(
+            ReplaceExpr::new(
+                "test message hello".into(),
+                Selection::First,
+                "message".into(),
+                "yo".into()
+            )
+            .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+            .unwrap()
+            .unwrap(),
+            "test yo hello"
+        );
+    }
+
+    #[test]
+    fn test_replace_first_2() {
+        assert_eq!(
+            ReplaceExpr::new(
+                "test message message hello".into(),
+                Selection::First,
+                "message".into(),
+                "yo".into()
+            )
+            .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+            .unwrap()
+            .unwrap(),
+            "test yo message hello"
+        );
+    }
+
+    #[test]
+    fn test_replace_last_1() {
+        assert_eq!(
+            ReplaceExpr::new(
+                "test message hello".into(),
+                Selection::Last,
+                "message".into(),
+                "yo".into()
+            )
+            .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+            .unwrap()
+            .unwrap(),
+            "test yo hello"
+        );
+    }
+
+    #[test]
+    fn test_replace_last_2() {
+        assert_eq!(
+            ReplaceExpr::new(
+                "test message message hello".into(),
+                Selection::Last,
+                "message".into(),
+                "yo".into()
+            )
+            .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+            .unwrap()
+            .unwrap(),
+            "test message yo hello"
+        );
+    }
+
+    #[test]
+    fn test_file_stem_1() {
+        assert_eq!(
+            FileStemExpr::new()
+                .execute(&mut engine_with_file("report.tar.gz"))
+                .unwrap()
+                .unwrap(),
+            "report.tar"
+        );
+    }
+
+    #[test]
+    fn test_file_stem_2() {
+        assert_eq!(
+            FileStemExpr::new()
+                .execute(&mut engine_with_file("notes"))
+                .unwrap()
+                .unwrap(),
+            "notes"
+        );
+    }
+
+    #[test]
+    fn test_lookup_1() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("titles.csv");
+
+        fs::write(
+            &csv,
+            "tt0133093, The Matrix\n\ntt0062622,2001: A Space Odyssey\n",
+        )
+        .unwrap();
+
+        let expr = LookupExpr::from_csv("tt0133093".into(), &csv, None).unwrap();
+
+        assert_eq!(
+            expr.execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "The Matrix"
+        );
+    }
+
+    #[test]
+    fn test_lookup_2() {
+        let expr = LookupExpr::new(
+            "tt0000000".into(),
+            HashMap::from([("tt0133093".to_string(), "The Matrix".to_string())]),
+            Some("Unknown".into()),
+        );
+
+        assert_eq!(
+            expr.execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "Unknown"
+        );
+    }
+
+    #[test]
+    fn test_lookup_3() {
+        let expr = LookupExpr::new(
+            "tt0000000".into(),
+            HashMap::from([("tt0133093".to_string(), "The Matrix".to_string())]),
+            None,
+        );
+
+        assert_eq!(
+            expr.execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_lookup_4() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("titles.csv");
+
+        fs::write(&csv, "tt0133093\n").unwrap();
+
+        assert!(matches!(
+            LookupExpr::from_csv("tt0133093".into(), &csv, None),
+            Err(Error::InvalidLookupTable(_))
+        ));
+        assert!(matches!(
+            LookupExpr::from_csv("tt0133093".into(), dir.path().join("missing.csv"), None),
+            Err(Error::ReadFileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_sibling_exists_1() {
+        let dir = tempfile::tempdir().unwrap();
+        let movie = dir.path().join("movie.mkv");
+
+        fs::write(&movie, "").unwrap();
+
+        let expr = SiblingExistsExpr::new(true, "srt".into());
+
+        assert_eq!(
+            expr.execute(&mut engine_with_file(movie.to_str().unwrap()))
+                .unwrap()
+                .unwrap(),
+            "false"
+        );
+
+        fs::write(dir.path().join("movie.srt"), "").unwrap();
+
+        assert_eq!(
+            expr.execute(&mut engine_with_file(movie.to_str().unwrap()))
+                .unwrap()
+                .unwrap(),
+            "true"
+        );
+    }
+
+    #[test]
+    fn test_sibling_exists_2() {
+        let dir = tempfile::tempdir().unwrap();
+        let movie = dir.path().join("movie.mkv");
+
+        fs::write(&movie, "").unwrap();
+        fs::write(dir.path().join("other.srt"), "").unwrap();
+
+        assert_eq!(
+            SiblingExistsExpr::new(true, ".srt".into())
+                .execute(&mut engine_with_file(movie.to_str().unwrap()))
+                .unwrap()
+                .unwrap(),
+            "false"
+        );
+        assert_eq!(
+            SiblingExistsExpr::new(false, ".srt".into())
+                .execute(&mut engine_with_file(movie.to_str().unwrap()))
+                .unwrap()
+                .unwrap(),
+            "true"
+        );
+    }
+
+    #[test]
+    fn test_file_meta_1() {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        engine
+            .process_file(
+                File::new("movies/tt0087182.mkv")
+                    .with_metadata("title", "Dune")
+                    .with_op(SetStemOperation::new(
+                        FileMetaExpr::new("title".to_string()).into(),
+                    )),
+            )
+            .unwrap();
+
+        assert_eq!(
+            engine.into_files()[0].destination_path_string(),
+            "movies/Dune.mkv"
+        );
+    }
+
+    #[test]
+    fn test_file_meta_2() {
+        assert_eq!(
+            FileMetaExpr::new("title".to_string())
+                .execute(&mut engine_with_file("movies/tt0087182.mkv"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parent_dir_name_1() {
+        assert_eq!(
+            ParentDirNameExpr::new(1)
+                .execute(&mut engine_with_file("a/b/c.txt"))
+                .unwrap()
+                .unwrap(),
+            "b"
+        );
+    }
+
+    #[test]
+    fn test_parent_dir_name_2() {
+        assert_eq!(
+            ParentDirNameExpr::new(2)
+                .execute(&mut engine_with_file("a/b/c.txt"))
+                .unwrap()
+                .unwrap(),
+            "a"
+        );
+    }
+
+    #[test]
+    fn test_parent_dir_name_3() {
+        assert_eq!(
+            ParentDirNameExpr::new(3)
+                .execute(&mut engine_with_file("a/b/c.txt"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_insert_index_1() {
+        assert_eq!(
+            InsertExpr::new(Position::Index(2), "café".into(), "_".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "ca_fé"
+        );
+    }
+
+    #[test]
+    fn test_insert_index_2() {
+        assert_eq!(
+            InsertExpr::new(Position::Index(4), "café".into(), "_1".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "café_1"
+        );
+    }
+
+    #[test]
+    fn test_insert_index_3() {
+        assert_eq!(
+            InsertExpr::new(Position::Index(10), "café".into(), "_1".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "café_1"
+        );
+    }
+
+    #[test]
+    fn test_insert_every_n_1() {
+        assert_eq!(
+            InsertExpr::new(Position::EveryN(2), "ABCD1234".into(), "-".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "AB-CD-12-34"
+        );
+    }
+
+    #[test]
+    fn test_insert_every_n_2() {
+        assert_eq!(
+            InsertExpr::new(Position::EveryN(2), "ABCDEFG".into(), "-".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "AB-CD-EF-G"
+        );
+    }
+
+    #[test]
+    fn test_insert_every_n_3() {
+        assert!(matches!(
+            InsertExpr::new(Position::EveryN(0), "ABCD".into(), "-".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new())),
+            Err(Error::ZeroInsertInterval)
+        ));
+    }
+
+    #[test]
+    fn test_overwrite_1() {
+        assert_eq!(
+            OverwriteExpr::new("IMG_0001.jpg".into(), 4, "9".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "IMG_9001.jpg"
+        );
+    }
+
+    #[test]
+    fn test_overwrite_2() {
+        assert_eq!(
+            OverwriteExpr::new("café".into(), 3, "és".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "cafés"
+        );
+    }
+
+    #[test]
+    fn test_overwrite_3() {
+        assert_eq!(
+            OverwriteExpr::new("ab".into(), 5, "cd".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "abcd"
+        );
+    }
+
+    #[test]
+    fn test_insert_index_from_end_1() {
+        assert_eq!(
+            InsertExpr::new(Position::IndexFromEnd(0), "name.txt".into(), "_1".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "name.txt_1"
+        );
+    }
+
+    #[test]
+    fn test_insert_index_from_end_2() {
+        assert_eq!(
+            InsertExpr::new(Position::IndexFromEnd(4), "name.txt".into(), "_1".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "name_1.txt"
+        );
+    }
 
-        assert_eq!(r, "test test yo hello hello");
+    #[test]
+    fn test_insert_index_from_end_3() {
+        assert_eq!(
+            InsertExpr::new(Position::IndexFromEnd(20), "name.txt".into(), "_1".into())
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+            "_1name.txt"
+        );
     }
 
     #[test]
-    fn test_replace_first_1() {
+    fn test_insert_after_all_1() {
         assert_eq!(
-            ReplaceExpr::new(
-                "test message hello".into(),
-                Selection::First,
-                "message".into(),
-                "yo".into()
+            InsertExpr::new(
+                Position::AfterAll("_".to_string()),
+                "a_b_c".into(),
+                " ".into()
             )
             .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
             .unwrap()
             .unwrap(),
-            "test yo hello"
+            "a_ b_ c"
         );
     }
 
     #[test]
-    fn test_replace_first_2() {
+    fn test_insert_before_all_1() {
         assert_eq!(
-            ReplaceExpr::new(
-                "test message message hello".into(),
-                Selection::First,
-                "message".into(),
-                "yo".into()
+            InsertExpr::new(
+                Position::BeforeAll("_".to_string()),
+                "a_b_c".into(),
+                " ".into()
             )
             .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
             .unwrap()
             .unwrap(),
-            "test yo message hello"
+            "a _b _c"
         );
     }
 
     #[test]
-    fn test_replace_last_1() {
+    fn test_insert_after_all_2() {
         assert_eq!(
-            ReplaceExpr::new(
-                "test message hello".into(),
-                Selection::Last,
-                "message".into(),
-                "yo".into()
+            InsertExpr::new(
+                Position::AfterAll("_".to_string()),
+                "abc".into(),
+                " ".into()
             )
             .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
             .unwrap()
             .unwrap(),
-            "test yo hello"
+            "abc"
         );
     }
 
     #[test]
-    fn test_replace_last_2() {
+    fn test_title_case_1() {
         assert_eq!(
-            ReplaceExpr::new(
-                "test message message hello".into(),
-                Selection::Last,
-                "message".into(),
-                "yo".into()
+            TitleCaseExpr::new(
+                "the lord of the rings".into(),
+                vec!["of".to_string(), "the".to_string()]
             )
             .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
             .unwrap()
             .unwrap(),
-            "test message yo hello"
+            "The Lord of the Rings"
+        );
+    }
+
+    #[test]
+    fn test_title_case_2() {
+        assert_eq!(
+            TitleCaseExpr::new(
+                "a history OF the NASA program".into(),
+                vec!["a".to_string(), "of".to_string(), "the".to_string()]
+            )
+            .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+            .unwrap()
+            .unwrap(),
+            "A History OF the NASA Program"
+        );
+    }
+
+    #[test]
+    fn test_path_hash_1() {
+        let id = |path: &str| {
+            PathHashExpr::new(8)
+                .execute(&mut engine_with_file(path))
+                .unwrap()
+                .unwrap()
+        };
+
+        assert_eq!(id("/data/a/photo.jpg").len(), 8);
+        assert_eq!(id("/data/a/photo.jpg"), id("/data/a/photo.jpg"));
+        assert_ne!(id("/data/a/photo.jpg"), id("/data/b/photo.jpg"));
+        assert!(id("/data/a/photo.jpg")
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase()));
+    }
+
+    #[test]
+    fn test_path_hash_2() {
+        assert_eq!(to_base36(0), "0000000000000");
+        assert_eq!(to_base36(u64::MAX), "3w5e11264sgsf");
+        assert_eq!(
+            PathHashExpr::new(40)
+                .execute(&mut engine_with_file("a.txt"))
+                .unwrap()
+                .unwrap()
+                .len(),
+            13
+        );
+    }
+
+    #[test]
+    fn test_source_path_1() {
+        assert_eq!(
+            SourcePathExpr::new(None, None)
+                .execute(&mut engine_with_file("/data/a/b.txt"))
+                .unwrap()
+                .unwrap(),
+            "/data/a/b.txt"
+        );
+    }
+
+    #[test]
+    fn test_source_path_2() {
+        assert_eq!(
+            SourcePathExpr::new(Some(PathBuf::from("/data")), Some("_".to_string()))
+                .execute(&mut engine_with_file("/data/a/b.txt"))
+                .unwrap()
+                .unwrap(),
+            "a_b.txt"
+        );
+    }
+
+    #[test]
+    fn test_source_path_3() {
+        assert_eq!(
+            SourcePathExpr::new(Some(PathBuf::from("/other")), None)
+                .execute(&mut engine_with_file("/data/a/b.txt"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_flatten_path_1() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/c.txt"), "").unwrap();
+        fs::write(root.join("a/d.txt"), "").unwrap();
+        fs::write(root.join("e.txt"), "").unwrap();
+
+        let tree = crate::RTBuilder::new()
+            .with_directory(crate::Dir::new(root, true))
+            .with_file_op(SetNameOperation::new(
+                FlattenPathExpr::new("_".to_string(), root.to_path_buf()).into(),
+            ))
+            .build_tree()
+            .unwrap();
+
+        let mut names: Vec<String> = tree
+            .planned()
+            .into_iter()
+            .map(|(_, d)| d.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        names.sort();
+
+        assert_eq!(names, vec!["a_b_c.txt", "a_d.txt", "e.txt"]);
+    }
+
+    #[test]
+    fn test_flatten_path_2() {
+        assert_eq!(
+            FlattenPathExpr::new("-".to_string(), PathBuf::from("/other"))
+                .execute(&mut engine_with_file("/data/a/b.txt"))
+                .unwrap(),
+            None
         );
     }
 
@@ -530,10 +2274,318 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_if_1() {
+        assert_eq!(
+            IfExpr::new(
+                MatchRule::Equals("txt".to_string()),
+                "text".into(),
+                Some("other".into()),
+            )
+            .with_subject(FileExtensionExpr::new().into())
+            .execute(&mut engine_with_file("notes.txt"))
+            .unwrap()
+            .unwrap(),
+            "text"
+        );
+    }
+
+    #[test]
+    fn test_if_2() {
+        assert_eq!(
+            IfExpr::new(
+                MatchRule::Equals("txt".to_string()),
+                "text".into(),
+                Some("other".into()),
+            )
+            .execute(&mut engine_with_file("notes.txt"))
+            .unwrap()
+            .unwrap(),
+            "other"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_if_3() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+        engine
+            .process_file(File::new(OsStr::from_bytes(b"notes\xff.txt")))
+            .unwrap();
+
+        assert!(matches!(
+            IfExpr::new(MatchRule::EndsWith(".txt".to_string()), "text".into(), None)
+                .execute(&mut engine),
+            Err(Error::CannotIdentifyFileName)
+        ));
+    }
+
+    #[cfg(feature = "datetime")]
+    mod datetime {
+        use super::*;
+
+        #[test]
+        fn test_now_1() {
+            let stamp = NowExpr::new("%Y%m%d-%H%M%S%.9f".to_string(), true);
+            let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+            let mut stamps = Vec::new();
+
+            for name in ["a.txt", "b.txt", "c.txt"] {
+                engine.process_file(File::new(name)).unwrap();
+                stamps.push(stamp.execute(&mut engine).unwrap().unwrap());
+
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+
+            assert_eq!(stamps[0].len(), "20230101-000000.000000000".len());
+            assert!(stamps.iter().all(|s| *s == stamps[0]));
+        }
+
+        #[test]
+        fn test_now_2() {
+            let dir = tempfile::tempdir().unwrap();
+
+            for name in ["a.zip", "b.zip", "c.zip"] {
+                fs::write(dir.path().join(name), "").unwrap();
+            }
+
+            let stamps: Vec<String> = crate::RTBuilder::new()
+                .with_directory(crate::Dir::new(dir.path(), false))
+                .with_file_op(SetNameOperation::new(
+                    InsertExpr::new(
+                        Position::Start,
+                        FileNameExpr::new().into(),
+                        NowExpr::new("%Y%m%d%H%M%S%.9f_".to_string(), false).into(),
+                    )
+                    .into(),
+                ))
+                .build_tree()
+                .unwrap()
+                .planned()
+                .into_iter()
+                .map(|(_, d)| {
+                    let name = d.file_name().unwrap().to_str().unwrap().to_string();
+
+                    return name.split('_').next().unwrap().to_string();
+                })
+                .collect();
+
+            assert_eq!(stamps.len(), 3);
+            assert!(stamps.iter().all(|s| *s == stamps[0]));
+        }
+
+        #[test]
+        fn test_parse_date_1() {
+            assert_eq!(
+                ParseDateExpr::new(
+                    "2023.01.15".into(),
+                    "%Y.%m.%d".to_string(),
+                    "%Y-%m-%d".to_string()
+                )
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+                "2023-01-15"
+            );
+        }
+
+        #[test]
+        fn test_parse_date_2() {
+            assert_eq!(
+                ParseDateExpr::new(
+                    "report_final".into(),
+                    "%Y.%m.%d".to_string(),
+                    "%Y-%m-%d".to_string()
+                )
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap(),
+                None
+            );
+        }
+    }
+
+    #[cfg(all(unix, feature = "xattr"))]
+    mod xattr {
+        use super::*;
+
+        #[test]
+        fn test_xattr_1() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track01.flac");
+
+            fs::write(&path, "").unwrap();
+            ::xattr::set(&path, "user.title", b"Blue in Green").unwrap();
+
+            let mut engine = engine_with_file(path.to_str().unwrap());
+
+            assert_eq!(
+                XattrExpr::new("user.title".to_string())
+                    .execute(&mut engine)
+                    .unwrap(),
+                Some("Blue in Green".to_string())
+            );
+            assert_eq!(
+                XattrExpr::new("user.artist".to_string())
+                    .execute(&mut engine)
+                    .unwrap(),
+                None
+            );
+        }
+    }
+
+    #[cfg(feature = "exif")]
+    mod exif {
+        use super::*;
+
+        // A minimal JPEG holding only an APP1 segment with DateTimeOriginal.
+        fn jpeg_with_date(date: &str) -> Vec<u8> {
+            let mut tiff = vec![b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00];
+            // IFD0 with a single pointer to the Exif IFD at offset 26.
+            tiff.extend_from_slice(&[0x01, 0x00, 0x69, 0x87, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00]);
+            tiff.extend_from_slice(&[26, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+            // Exif IFD with DateTimeOriginal stored at offset 44.
+            tiff.extend_from_slice(&[0x01, 0x00, 0x03, 0x90, 0x02, 0x00, 20, 0x00, 0x00, 0x00]);
+            tiff.extend_from_slice(&[44, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+            tiff.extend_from_slice(date.as_bytes());
+            tiff.push(0);
+
+            let len = (tiff.len() + 8) as u16;
+
+            let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+            jpeg.extend_from_slice(&len.to_be_bytes());
+            jpeg.extend_from_slice(b"Exif\0\0");
+            jpeg.extend_from_slice(&tiff);
+            jpeg.extend_from_slice(&[0xFF, 0xD9]);
+
+            return jpeg;
+        }
+
+        #[test]
+        fn test_exif_date_1() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("IMG_0001.jpg");
+
+            fs::write(&path, jpeg_with_date("2021:07:04 15:30:00")).unwrap();
+
+            assert_eq!(
+                ExifDateExpr::new("%Y-%m-%d_%H%M%S".to_string())
+                    .execute(&mut engine_with_file(path.to_str().unwrap()))
+                    .unwrap()
+                    .unwrap(),
+                "2021-07-04_153000"
+            );
+        }
+
+        #[test]
+        fn test_exif_date_2() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("IMG_0002.jpg");
+
+            fs::write(&path, [0xFF, 0xD8, 0xFF, 0xD9]).unwrap();
+
+            assert_eq!(
+                ExifDateExpr::new("%Y-%m-%d".to_string())
+                    .execute(&mut engine_with_file(path.to_str().unwrap()))
+                    .unwrap(),
+                None
+            );
+        }
+    }
+
     #[cfg(feature = "regex_match")]
     mod regex {
         use super::*;
 
+        #[test]
+        fn test_season_episode_1() {
+            assert_eq!(
+                SeasonEpisodeExpr::new(
+                    "Show.S01E02.mkv".into(),
+                    "{season}x{episode:2}".to_string()
+                )
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+                "1x02"
+            );
+        }
+
+        #[test]
+        fn test_season_episode_2() {
+            assert_eq!(
+                SeasonEpisodeExpr::new(
+                    "Show - 1x02 - Pilot.mkv".into(),
+                    "S{season:2}E{episode:2}".to_string()
+                )
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap(),
+                "S01E02"
+            );
+        }
+
+        #[test]
+        fn test_season_episode_3() {
+            assert_eq!(
+                SeasonEpisodeExpr::new("Holiday 2019.mkv".into(), "{season}x{episode}".to_string())
+                    .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                    .unwrap(),
+                None
+            );
+        }
+
+        fn track(input: &str, mode: TrackNumberMode) -> String {
+            return TrackNumberExpr::new(input.into(), mode)
+                .execute(&mut OperationEngine::new(Vec::new(), Vec::new()))
+                .unwrap()
+                .unwrap();
+        }
+
+        #[test]
+        fn test_track_number_1() {
+            assert_eq!(track("01 - Song.mp3", TrackNumberMode::Strip), "Song.mp3");
+            assert_eq!(track("1. Song.mp3", TrackNumberMode::Strip), "Song.mp3");
+            assert_eq!(track("07_Song.mp3", TrackNumberMode::Strip), "Song.mp3");
+        }
+
+        #[test]
+        fn test_track_number_2() {
+            assert_eq!(
+                track("01 - Song.mp3", TrackNumberMode::Pad(2)),
+                "01 Song.mp3"
+            );
+            assert_eq!(track("1. Song.mp3", TrackNumberMode::Pad(2)), "01 Song.mp3");
+            assert_eq!(track("1 Song.mp3", TrackNumberMode::Pad(3)), "001 Song.mp3");
+        }
+
+        #[test]
+        fn test_track_number_3() {
+            let mode = TrackNumberMode::Reformat("Track {track:2} - ".to_string());
+
+            assert_eq!(track("1. Song.mp3", mode.clone()), "Track 01 - Song.mp3");
+            assert_eq!(track("12) Song.mp3", mode), "Track 12 - Song.mp3");
+        }
+
+        #[test]
+        fn test_track_number_4() {
+            assert_eq!(track("Song.mp3", TrackNumberMode::Strip), "Song.mp3");
+            assert_eq!(
+                track("1999 Live.mp3", TrackNumberMode::Pad(2)),
+                "1999 Live.mp3"
+            );
+            assert_eq!(track("2 2.mp3", TrackNumberMode::Strip), "2 2.mp3");
+        }
+
+        #[test]
+        fn test_track_number_5() {
+            assert_eq!(track("01.mp3", TrackNumberMode::Strip), "01.mp3");
+            assert_eq!(track("01.mp3", TrackNumberMode::Pad(2)), "01.mp3");
+            assert_eq!(track("1. Song", TrackNumberMode::Strip), "Song");
+        }
+
         #[test]
         fn test_insert_before_1() {
             let r = Regex::new("test").unwrap();
@@ -568,6 +2620,53 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_capture_to_variable_1() {
+            let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+            assert_eq!(
+                CaptureToVariableExpr::new(
+                    Regex::new(r"\((\d{4})\)").unwrap(),
+                    1,
+                    "year".to_string(),
+                    "Movie (1999).mkv".into()
+                )
+                .execute(&mut engine)
+                .unwrap()
+                .unwrap(),
+                "1999"
+            );
+
+            assert_eq!(
+                VariableExpr::new("year".to_string())
+                    .execute(&mut engine)
+                    .unwrap()
+                    .unwrap(),
+                "1999"
+            );
+        }
+
+        #[test]
+        fn test_capture_to_variable_2() {
+            let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+            assert_eq!(
+                CaptureToVariableExpr::new(
+                    Regex::new(r"\((\d{4})\)").unwrap(),
+                    1,
+                    "year".to_string(),
+                    "Movie.mkv".into()
+                )
+                .execute(&mut engine)
+                .unwrap(),
+                None
+            );
+
+            assert!(VariableExpr::new("year".to_string())
+                .execute(&mut engine)
+                .is_err());
+        }
+
         #[test]
         fn test_match_1() {
             let r = Regex::new(r"\[.*\]").unwrap();
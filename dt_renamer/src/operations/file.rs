@@ -1,3 +1,12 @@
+use std::collections::HashMap;
+#[cfg(feature = "hash")]
+use std::fs;
+#[cfg(feature = "hash")]
+use std::io::Read;
+use std::path::Path;
+
+use convert_case::{Case, Casing};
+
 use crate::error::Error;
 use crate::operations::operation::Expression;
 use crate::operations::{FileOperation, MatchRule};
@@ -10,6 +19,10 @@ define_opexp_skeleton!(set_name_operation, name: Box<dyn Expression>);
 define_opexp_skeleton!(set_stem_operation, stem: Box<dyn Expression>);
 define_opexp_skeleton!(set_extension_operation, extension: Box<dyn Expression>);
 define_opexp_skeleton!(no_op_operation, expression: Box<dyn Expression>);
+define_opexp_skeleton!(normalize_extension_operation, lowercase: bool, map: HashMap<String, String>);
+define_opexp_skeleton!(prettify_name_operation, case: Case, lowercase_extension: bool);
+#[cfg(feature = "hash")]
+define_opexp_skeleton!(checksum_suffix_operation, length: usize, separator: String);
 
 impl FileOperation for NoOpOperation {
     fn execute(&self, engine: &mut OperationEngine) -> Result<bool, Error> {
@@ -23,9 +36,8 @@ impl FileOperation for NoOpOperation {
 
 impl FileOperation for IfOperation {
     fn execute(&self, engine: &mut OperationEngine) -> Result<bool, Error> {
-        let cond = self
-            .condition
-            .resolve(&engine.current_file().destination_path_string());
+        let subject = engine.current_file().destination_path_string();
+        let cond = engine.resolve(&self.condition, &subject);
 
         if cond {
             return self.then_op.execute(engine);
@@ -37,6 +49,11 @@ impl FileOperation for IfOperation {
     }
 
     clone_dyn!(FileOperation);
+
+    fn mutates_name(&self) -> bool {
+        return self.then_op.mutates_name()
+            || self.else_op.as_ref().is_some_and(|op| op.mutates_name());
+    }
 }
 
 impl FileOperation for SetNameOperation {
@@ -44,42 +61,56 @@ impl FileOperation for SetNameOperation {
         let res = self.name.execute(engine)?;
 
         let Some(name) = res else {
+            engine.mark_name_missing();
+
             return Ok(false);
         };
 
-        engine.current_file().destination.set_file_name(name);
+        engine.set_current_name(&name)?;
 
         return Ok(true);
     }
 
     clone_dyn!(FileOperation);
+
+    fn mutates_name(&self) -> bool {
+        return true;
+    }
+
+    fn overwrites_name(&self) -> bool {
+        return self.name.is_constant();
+    }
 }
 
 impl FileOperation for SetStemOperation {
     fn execute(&self, engine: &mut OperationEngine) -> Result<bool, Error> {
         let res = self.stem.execute(engine)?;
 
-        let Some(name) = res else {
+        let Some(stem) = res else {
             return Ok(false);
         };
 
-        if let Some(extension) = engine.current_file().destination.extension().map(|r| {
-            r.to_str()
-                .ok_or(Error::CannotIdentifyFileExtension)
-                .map(|s| s.to_string())
-        }) {
-            engine
-                .current_file()
-                .destination
-                .set_file_name(format!("{}.{}", name, extension?));
-        } else {
-            engine.current_file().destination.set_file_name(name);
+        let current = engine.current_name().ok_or(Error::CannotIdentifyFileName)?;
+
+        match Path::new(&current).extension() {
+            Some(extension) => {
+                let extension = extension
+                    .to_str()
+                    .ok_or(Error::CannotIdentifyFileExtension)?;
+
+                engine.set_current_name(&format!("{}.{}", stem, extension))?;
+            }
+            None => engine.set_current_name(&stem)?,
         }
 
         return Ok(true);
     }
 
     clone_dyn!(FileOperation);
+
+    fn mutates_name(&self) -> bool {
+        return true;
+    }
 }
 
 impl FileOperation for SetExtensionOperation {
@@ -96,4 +127,247 @@ impl FileOperation for SetExtensionOperation {
     }
 
     clone_dyn!(FileOperation);
+
+    fn mutates_name(&self) -> bool {
+        return true;
+    }
+}
+
+impl FileOperation for NormalizeExtensionOperation {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<bool, Error> {
+        let destination = &mut engine.current_file().destination;
+
+        let Some(extension) = destination.extension().and_then(|e| e.to_str()) else {
+            return Ok(false);
+        };
+
+        let key = match self.lowercase {
+            true => extension.to_lowercase(),
+            false => extension.to_string(),
+        };
+        let normalized = self.map.get(&key).cloned().unwrap_or(key);
+
+        if normalized == extension {
+            return Ok(false);
+        }
+
+        destination.set_extension(normalized);
+
+        return Ok(true);
+    }
+
+    clone_dyn!(FileOperation);
+
+    fn mutates_name(&self) -> bool {
+        return true;
+    }
+}
+
+impl FileOperation for PrettifyNameOperation {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<bool, Error> {
+        let destination = &mut engine.current_file().destination;
+
+        let stem = destination
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or(Error::CannotIdentifyFileName)?
+            .to_case(self.case);
+
+        let name = match destination.extension() {
+            Some(extension) => {
+                let extension = extension
+                    .to_str()
+                    .ok_or(Error::CannotIdentifyFileExtension)?;
+
+                match self.lowercase_extension {
+                    true => format!("{}.{}", stem, extension.to_lowercase()),
+                    false => format!("{}.{}", stem, extension),
+                }
+            }
+            None => stem,
+        };
+
+        destination.set_file_name(name);
+
+        return Ok(true);
+    }
+
+    clone_dyn!(FileOperation);
+
+    fn mutates_name(&self) -> bool {
+        return true;
+    }
+}
+
+// The CRC32 is of the source file's contents, written as 8 lowercase hex
+// digits and truncated to `length`.
+#[cfg(feature = "hash")]
+impl FileOperation for ChecksumSuffixOperation {
+    fn execute(&self, engine: &mut OperationEngine) -> Result<bool, Error> {
+        let file = engine.current_file();
+
+        let mut source = fs::File::open(&file.source).map_err(|e| Error::ReadFileError(e))?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = [0; 8192];
+
+        loop {
+            let n = source.read(&mut buf).map_err(|e| Error::ReadFileError(e))?;
+
+            if n == 0 {
+                break;
+            }
+
+            hasher.update(&buf[..n]);
+        }
+
+        let mut checksum = format!("{:08x}", hasher.finalize());
+        checksum.truncate(self.length);
+
+        let stem = file
+            .destination
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or(Error::CannotIdentifyFileName)?;
+
+        let name = match file.destination.extension() {
+            Some(extension) => format!(
+                "{}{}{}.{}",
+                stem,
+                self.separator,
+                checksum,
+                extension
+                    .to_str()
+                    .ok_or(Error::CannotIdentifyFileExtension)?
+            ),
+            None => format!("{}{}{}", stem, self.separator, checksum),
+        };
+
+        file.destination.set_file_name(name);
+
+        return Ok(true);
+    }
+
+    clone_dyn!(FileOperation);
+
+    fn mutates_name(&self) -> bool {
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(name: &str, op: NormalizeExtensionOperation) -> String {
+        return OperationEngine::new(Vec::new(), Vec::new())
+            .transform_name(name, &[op.into()])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_normalize_extension_1() {
+        assert_eq!(
+            normalize(
+                "Holiday.JPEG",
+                NormalizeExtensionOperation::new(
+                    true,
+                    HashMap::from([("jpeg".to_string(), "jpg".to_string())])
+                )
+            ),
+            "Holiday.jpg"
+        );
+    }
+
+    #[test]
+    fn test_normalize_extension_2() {
+        assert_eq!(
+            normalize(
+                "Holiday.JPG",
+                NormalizeExtensionOperation::new(true, HashMap::new())
+            ),
+            "Holiday.jpg"
+        );
+    }
+
+    #[test]
+    fn test_normalize_extension_3() {
+        assert_eq!(
+            normalize(
+                "README",
+                NormalizeExtensionOperation::new(true, HashMap::new())
+            ),
+            "README"
+        );
+    }
+
+    fn prettify(name: &str, op: PrettifyNameOperation) -> String {
+        return OperationEngine::new(Vec::new(), Vec::new())
+            .transform_name(name, &[op.into()])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_prettify_name_1() {
+        assert_eq!(
+            prettify(
+                "the song.MP3",
+                PrettifyNameOperation::new(Case::Title, true)
+            ),
+            "The Song.mp3"
+        );
+    }
+
+    #[test]
+    fn test_prettify_name_2() {
+        assert_eq!(
+            prettify(
+                "Holiday Photos.JPG",
+                PrettifyNameOperation::new(Case::Snake, false)
+            ),
+            "holiday_photos.JPG"
+        );
+        assert_eq!(
+            prettify("my notes", PrettifyNameOperation::new(Case::Kebab, true)),
+            "my-notes"
+        );
+    }
+
+    #[cfg(feature = "hash")]
+    mod checksum {
+        use super::*;
+        use crate::File;
+
+        fn suffixed(name: &str, op: ChecksumSuffixOperation) -> String {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(name);
+
+            fs::write(&path, "hello world").unwrap();
+
+            let mut engine = OperationEngine::new(Vec::new(), vec![op.into()]);
+            engine.process_file(File::new(&path)).unwrap();
+
+            return engine.into_files()[0]
+                .destination
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+        }
+
+        #[test]
+        fn test_checksum_suffix_1() {
+            assert_eq!(
+                suffixed("data.bin", ChecksumSuffixOperation::new(8, ".".to_string())),
+                "data.0d4a1185.bin"
+            );
+        }
+
+        #[test]
+        fn test_checksum_suffix_2() {
+            assert_eq!(
+                suffixed("README", ChecksumSuffixOperation::new(4, "-".to_string())),
+                "README-0d4a"
+            );
+        }
+    }
 }
@@ -1,53 +1,176 @@
+use std::borrow::Cow;
+
 #[cfg(feature = "regex_match")]
 use regex::Regex;
 
+use crate::error::Error;
+#[cfg(feature = "infer")]
+use crate::operations::supporting_objects::Category;
+
+/// The pattern held by [`MatchRule::Matches`]: a compiled regex with the
+/// `regex_match` feature, otherwise the literal text.
+#[cfg(feature = "regex_match")]
+pub type MatchPattern = Regex;
+#[cfg(not(feature = "regex_match"))]
+pub type MatchPattern = String;
+
 #[derive(Clone, Debug)]
 #[cfg_attr(not(feature = "regex_match"), derive(PartialEq, Eq, Hash))]
 pub enum MatchRule {
     #[cfg(feature = "regex_match")]
     Find(Regex),
+    /// A pattern built with [`MatchRule::matches`]. Without the `regex_match`
+    /// feature this degrades to a literal substring match.
+    Matches(MatchPattern),
     Equals(String),
     Contains(String),
     BeginsWith(String),
     EndsWith(String),
+    BeginsWithAny(Vec<String>),
+    EndsWithAny(Vec<String>),
     Not(Box<MatchRule>),
     And(Box<MatchRule>, Box<MatchRule>),
     Or(Box<MatchRule>, Box<MatchRule>),
+    All(Vec<MatchRule>),
+    Any(Vec<MatchRule>),
+    /// Treats the input as a file path and matches when the file's contents
+    /// belong to the category. Unreadable files never match.
+    #[cfg(feature = "infer")]
+    MimeCategory(Category),
 }
 
 impl MatchRule {
+    #[cfg(feature = "regex_match")]
+    pub fn regex(pattern: &str) -> Result<MatchRule, Error> {
+        return Regex::new(pattern)
+            .map(MatchRule::Find)
+            .map_err(|e| Error::InvalidRegex(e.to_string()));
+    }
+
+    /// Builds a [`MatchRule::Matches`] rule, compiling `pattern` once when
+    /// `regex_match` is enabled.
+    pub fn matches(pattern: &str) -> Result<MatchRule, Error> {
+        #[cfg(feature = "regex_match")]
+        return Regex::new(pattern)
+            .map(MatchRule::Matches)
+            .map_err(|e| Error::InvalidRegex(e.to_string()));
+        #[cfg(not(feature = "regex_match"))]
+        return Ok(MatchRule::Matches(pattern.to_string()));
+    }
+
+    /// Eliminates double negations and pushes `Not` inwards through
+    /// `And`/`Or`/`All`/`Any` using De Morgan's laws.
+    pub fn simplify(self) -> MatchRule {
+        let negate = |r: MatchRule| MatchRule::Not(Box::new(r));
+
+        return match self {
+            MatchRule::Not(inner) => match *inner {
+                MatchRule::Not(r) => r.simplify(),
+                MatchRule::And(a, b) => {
+                    MatchRule::Or(Box::new(negate(*a)), Box::new(negate(*b))).simplify()
+                }
+                MatchRule::Or(a, b) => {
+                    MatchRule::And(Box::new(negate(*a)), Box::new(negate(*b))).simplify()
+                }
+                MatchRule::All(rules) => {
+                    MatchRule::Any(rules.into_iter().map(negate).collect()).simplify()
+                }
+                MatchRule::Any(rules) => {
+                    MatchRule::All(rules.into_iter().map(negate).collect()).simplify()
+                }
+                r => negate(r),
+            },
+            MatchRule::And(a, b) => MatchRule::And(Box::new(a.simplify()), Box::new(b.simplify())),
+            MatchRule::Or(a, b) => MatchRule::Or(Box::new(a.simplify()), Box::new(b.simplify())),
+            MatchRule::All(mut rules) if rules.len() == 1 => rules.remove(0).simplify(),
+            MatchRule::Any(mut rules) if rules.len() == 1 => rules.remove(0).simplify(),
+            MatchRule::All(rules) => {
+                MatchRule::All(rules.into_iter().map(|r| r.simplify()).collect())
+            }
+            MatchRule::Any(rules) => {
+                MatchRule::Any(rules.into_iter().map(|r| r.simplify()).collect())
+            }
+            r => r,
+        };
+    }
+
     pub fn resolve(&self, input: &String) -> bool {
+        return self.resolve_with_case(input, false);
+    }
+
+    /// Like [`MatchRule::resolve`], optionally comparing the literal rules
+    /// case-insensitively. Regex rules are unaffected and keep their own
+    /// flags, e.g. `(?i)`.
+    pub fn resolve_with_case(&self, input: &String, case_insensitive: bool) -> bool {
+        let fold = |s| fold_case(s, case_insensitive);
+
         match self {
+            #[cfg(feature = "regex_match")]
             MatchRule::Find(reg) => return reg.is_match(input),
-            MatchRule::Equals(s) => return input == s,
-            MatchRule::Contains(s) => {
-                if s.len() > input.len() {
-                    return false;
-                }
-
-                return input.find(s).is_some();
+            #[cfg(feature = "regex_match")]
+            MatchRule::Matches(reg) => return reg.is_match(input),
+            #[cfg(not(feature = "regex_match"))]
+            MatchRule::Matches(pattern) => return input.contains(pattern.as_str()),
+            MatchRule::Equals(s) => return fold(input) == fold(s),
+            MatchRule::Contains(s) => return fold(input).contains(fold(s).as_ref()),
+            MatchRule::BeginsWith(s) => return fold(input).starts_with(fold(s).as_ref()),
+            MatchRule::EndsWith(s) => return fold(input).ends_with(fold(s).as_ref()),
+            MatchRule::BeginsWithAny(v) => {
+                return v.iter().any(|s| fold(input).starts_with(fold(s).as_ref()));
             }
-            MatchRule::BeginsWith(s) => {
-                if s.len() > input.len() {
-                    return false;
-                }
-
-                return &input[0..s.len()] == s;
+            MatchRule::EndsWithAny(v) => {
+                return v.iter().any(|s| fold(input).ends_with(fold(s).as_ref()));
             }
-            MatchRule::EndsWith(s) => {
-                if s.len() > input.len() {
-                    return false;
-                }
-
-                return &input[input.len() - s.len()..] == s;
+            MatchRule::And(r1, r2) => {
+                return r1.resolve_with_case(input, case_insensitive)
+                    && r2.resolve_with_case(input, case_insensitive);
+            }
+            MatchRule::Or(r1, r2) => {
+                return r1.resolve_with_case(input, case_insensitive)
+                    || r2.resolve_with_case(input, case_insensitive);
+            }
+            MatchRule::Not(r) => return !r.resolve_with_case(input, case_insensitive),
+            MatchRule::All(rules) => {
+                return rules
+                    .iter()
+                    .all(|r| r.resolve_with_case(input, case_insensitive));
+            }
+            MatchRule::Any(rules) => {
+                return rules
+                    .iter()
+                    .any(|r| r.resolve_with_case(input, case_insensitive));
+            }
+            #[cfg(feature = "infer")]
+            MatchRule::MimeCategory(category) => {
+                return detect_category(input).is_some_and(|c| c == *category);
             }
-            MatchRule::And(r1, r2) => return r1.resolve(input) && r2.resolve(input),
-            MatchRule::Or(r1, r2) => return r1.resolve(input) || r2.resolve(input),
-            MatchRule::Not(r) => return !r.resolve(input),
         };
     }
 }
 
+fn fold_case(s: &str, case_insensitive: bool) -> Cow<'_, str> {
+    return match case_insensitive {
+        true => Cow::Owned(s.to_lowercase()),
+        false => Cow::Borrowed(s),
+    };
+}
+
+#[cfg(feature = "infer")]
+fn detect_category(path: &str) -> Option<Category> {
+    use infer::MatcherType;
+
+    let kind = infer::get_from_path(path).ok()?;
+
+    return Some(match kind.map(|k| k.matcher_type()) {
+        Some(MatcherType::Image) => Category::Image,
+        Some(MatcherType::Video) => Category::Video,
+        Some(MatcherType::Audio) => Category::Audio,
+        Some(MatcherType::Doc | MatcherType::Book | MatcherType::Text) => Category::Document,
+        Some(MatcherType::Archive) => Category::Archive,
+        _ => Category::Other,
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -55,6 +178,73 @@ mod tests {
     mod match_rule {
         use super::*;
 
+        fn contains(s: &str) -> MatchRule {
+            return MatchRule::Contains(s.to_string());
+        }
+
+        fn not(r: MatchRule) -> MatchRule {
+            return MatchRule::Not(Box::new(r));
+        }
+
+        #[test]
+        fn test_resolve_with_case_1() {
+            let rule = MatchRule::Any(vec![
+                MatchRule::BeginsWith("IMG".to_string()),
+                MatchRule::EndsWith(".raw".to_string()),
+            ]);
+
+            assert!(rule.resolve_with_case(&"img_01.jpg".to_string(), true));
+            assert!(rule.resolve_with_case(&"DSC_01.RAW".to_string(), true));
+            assert!(!rule.resolve_with_case(&"img_01.jpg".to_string(), false));
+            assert!(!rule.resolve(&"DSC_01.RAW".to_string()));
+        }
+
+        #[test]
+        fn test_simplify_1() {
+            return assert_eq!(
+                format!("{:?}", not(not(contains("a"))).simplify()),
+                format!("{:?}", contains("a"))
+            );
+        }
+
+        #[test]
+        fn test_simplify_2() {
+            let rule = not(MatchRule::And(
+                Box::new(contains("a")),
+                Box::new(not(contains("b"))),
+            ));
+
+            return assert_eq!(
+                format!("{:?}", rule.simplify()),
+                format!(
+                    "{:?}",
+                    MatchRule::Or(Box::new(not(contains("a"))), Box::new(contains("b")))
+                )
+            );
+        }
+
+        #[test]
+        fn test_simplify_3() {
+            let rule = not(MatchRule::Any(vec![contains("a"), not(contains("b"))]));
+
+            let simplified = rule.clone().simplify();
+
+            for input in ["a", "b", "ab", "c"] {
+                assert_eq!(
+                    simplified.resolve(&input.to_string()),
+                    rule.resolve(&input.to_string())
+                );
+            }
+
+            return assert_eq!(
+                format!("{:?}", simplified),
+                format!(
+                    "{:?}",
+                    MatchRule::All(vec![not(contains("a")), contains("b")])
+                )
+            );
+        }
+
         #[test]
         fn test_equals_1() {
             return assert!(MatchRule::Equals("test".to_string()).resolve(&"test".to_string()));
@@ -146,6 +336,52 @@ mod tests {
             return assert!(MatchRule::EndsWith("st".to_string()).resolve(&"test".to_string()));
         }
 
+        #[test]
+        fn test_begins_with_any_1() {
+            return assert!(
+                MatchRule::BeginsWithAny(vec!["car".to_string(), "te".to_string()])
+                    .resolve(&"test".to_string())
+            );
+        }
+
+        #[test]
+        fn test_begins_with_any_2() {
+            return assert!(
+                !MatchRule::BeginsWithAny(vec!["car".to_string(), "st".to_string()])
+                    .resolve(&"test".to_string())
+            );
+        }
+
+        #[test]
+        fn test_begins_with_any_3() {
+            return assert!(!MatchRule::BeginsWithAny(Vec::new()).resolve(&"test".to_string()));
+        }
+
+        #[test]
+        fn test_ends_with_any_1() {
+            return assert!(MatchRule::EndsWithAny(vec![
+                ".jpg".to_string(),
+                ".png".to_string(),
+                ".gif".to_string()
+            ])
+            .resolve(&"image.png".to_string()));
+        }
+
+        #[test]
+        fn test_ends_with_any_2() {
+            return assert!(!MatchRule::EndsWithAny(vec![
+                ".jpg".to_string(),
+                ".png".to_string(),
+                ".gif".to_string()
+            ])
+            .resolve(&"notes.txt".to_string()));
+        }
+
+        #[test]
+        fn test_ends_with_any_3() {
+            return assert!(!MatchRule::EndsWithAny(Vec::new()).resolve(&"image.png".to_string()));
+        }
+
         #[test]
         fn test_and_1() {
             return assert!(MatchRule::And(
@@ -179,10 +415,110 @@ mod tests {
                 .resolve(&"test".to_string()));
         }
 
+        #[test]
+        fn test_all_1() {
+            return assert!(MatchRule::All(Vec::new()).resolve(&"test".to_string()));
+        }
+
+        #[test]
+        fn test_all_2() {
+            return assert!(MatchRule::All(vec![
+                MatchRule::BeginsWith("te".to_string()),
+                MatchRule::EndsWith("st".to_string()),
+                MatchRule::Not(MatchRule::Equals("car".to_string()).into()),
+            ])
+            .resolve(&"test".to_string()));
+        }
+
+        #[test]
+        fn test_all_3() {
+            return assert!(!MatchRule::All(vec![
+                MatchRule::BeginsWith("te".to_string()),
+                MatchRule::EndsWith("car".to_string()),
+            ])
+            .resolve(&"test".to_string()));
+        }
+
+        #[test]
+        fn test_any_1() {
+            return assert!(!MatchRule::Any(Vec::new()).resolve(&"test".to_string()));
+        }
+
+        #[test]
+        fn test_any_2() {
+            return assert!(MatchRule::Any(vec![
+                MatchRule::Equals("car".to_string()),
+                MatchRule::EndsWith("st".to_string()),
+            ])
+            .resolve(&"test".to_string()));
+        }
+
+        #[test]
+        fn test_any_3() {
+            return assert!(!MatchRule::Any(vec![
+                MatchRule::Equals("car".to_string()),
+                MatchRule::Contains("x".to_string()),
+            ])
+            .resolve(&"test".to_string()));
+        }
+
+        #[cfg(not(feature = "regex_match"))]
+        #[test]
+        fn test_matches_literal_1() {
+            return assert!(MatchRule::matches("st (19")
+                .unwrap()
+                .resolve(&"test (1922).mkv".to_string()));
+        }
+
+        #[cfg(not(feature = "regex_match"))]
+        #[test]
+        fn test_matches_literal_2() {
+            return assert!(!MatchRule::matches(r"\d{4}")
+                .unwrap()
+                .resolve(&"test (1922).mkv".to_string()));
+        }
+
         #[cfg(feature = "regex_match")]
         mod regex {
             use super::*;
 
+            #[test]
+            fn test_regex_1() {
+                return assert!(MatchRule::regex(r"\(\d{4}\)")
+                    .unwrap()
+                    .resolve(&"test (1922).mkv".to_string()));
+            }
+
+            #[test]
+            fn test_regex_2() {
+                return assert!(matches!(
+                    MatchRule::regex(r"("),
+                    Err(Error::InvalidRegex(_))
+                ));
+            }
+
+            #[test]
+            fn test_matches_pattern_1() {
+                return assert!(MatchRule::matches(r"\(\d{4}\)")
+                    .unwrap()
+                    .resolve(&"test (1922).mkv".to_string()));
+            }
+
+            #[test]
+            fn test_matches_pattern_2() {
+                return assert!(!MatchRule::matches(r"\(\d{4}\)")
+                    .unwrap()
+                    .resolve(&"test (19).mkv".to_string()));
+            }
+
+            #[test]
+            fn test_matches_pattern_3() {
+                return assert!(matches!(
+                    MatchRule::matches(r"("),
+                    Err(Error::InvalidRegex(_))
+                ));
+            }
+
             #[test]
             fn test_matches_1() {
                 return assert!(MatchRule::Find(
@@ -208,4 +544,83 @@ mod tests {
             }
         }
     }
+
+    #[cfg(feature = "infer")]
+    mod mime_category {
+        use super::*;
+        use crate::operations::directory::RegroupOperation;
+        use crate::operations::expressions::{IfExpr, SourcePathExpr};
+        use crate::{Dir, RTBuilder};
+        use std::fs;
+        use std::path::Path;
+
+        const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+        const MP4: &[u8] = b"\0\0\0\x18ftypmp42\0\0\0\0mp42isom";
+
+        fn fixtures(root: &Path) {
+            fs::write(root.join("photo.dat"), PNG).unwrap();
+            fs::write(root.join("clip.bin"), MP4).unwrap();
+            fs::write(root.join("notes.txt"), "plain text").unwrap();
+        }
+
+        fn resolve(rule: &MatchRule, path: &Path) -> bool {
+            return rule.resolve(&path.to_str().unwrap().to_string());
+        }
+
+        #[test]
+        fn test_mime_category_1() {
+            let dir = tempfile::tempdir().unwrap();
+            fixtures(dir.path());
+
+            let image = MatchRule::MimeCategory(Category::Image);
+            let video = MatchRule::MimeCategory(Category::Video);
+            let other = MatchRule::MimeCategory(Category::Other);
+
+            assert!(resolve(&image, &dir.path().join("photo.dat")));
+            assert!(!resolve(&video, &dir.path().join("photo.dat")));
+            assert!(resolve(&video, &dir.path().join("clip.bin")));
+            assert!(resolve(&other, &dir.path().join("notes.txt")));
+            assert!(!resolve(&other, &dir.path().join("missing.txt")));
+        }
+
+        #[test]
+        fn test_mime_category_2() {
+            let dir = tempfile::tempdir().unwrap();
+            let root = dir.path().canonicalize().unwrap();
+            fixtures(&root);
+
+            let folder = IfExpr::new(
+                MatchRule::MimeCategory(Category::Image),
+                "images".into(),
+                Some(
+                    IfExpr::new(
+                        MatchRule::MimeCategory(Category::Video),
+                        "videos".into(),
+                        Some("other".into()),
+                    )
+                    .with_subject(SourcePathExpr::new(None, None).into())
+                    .into(),
+                ),
+            )
+            .with_subject(SourcePathExpr::new(None, None).into());
+
+            let mut planned = RTBuilder::new()
+                .with_directory(
+                    Dir::new(&root, false).with_dir_op(RegroupOperation::new(folder.into())),
+                )
+                .build_tree()
+                .unwrap()
+                .planned();
+            planned.sort();
+
+            assert_eq!(
+                planned,
+                vec![
+                    (root.join("clip.bin"), root.join("videos/clip.bin")),
+                    (root.join("notes.txt"), root.join("other/notes.txt")),
+                    (root.join("photo.dat"), root.join("images/photo.dat")),
+                ]
+            );
+        }
+    }
 }
@@ -3,6 +3,7 @@ pub mod expressions;
 pub mod file;
 mod match_rule;
 mod operation;
+pub mod pipeline;
 pub mod supporting_objects;
 
 pub use match_rule::*;
@@ -5,10 +5,26 @@ use crate::{File, OperationEngine};
 
 #[macro_export]
 macro_rules! define_opexp_skeleton {
+    // Without fields `new` takes no arguments, which clippy's
+    // `new_without_default` wants paired with a `Default` impl.
+    ($name:ident) => {
+        paste::paste! {
+            #[derive(Debug, Clone, Default)]
+            #[cfg_attr(not(feature = "regex_match"), derive(PartialEq, Eq, Hash))]
+            pub struct [< $name:camel >] {}
+
+            impl [< $name:camel >] {
+                pub fn new() -> Self {
+                    return Self {};
+                }
+            }
+        }
+    };
     ($name:ident $(, $n:ident : $t:ty)*) => {
         paste::paste! {
+            // Fields may be boxed expressions or operations, which can't be
+            // compared or hashed, so only `Debug` and `Clone` are derived.
             #[derive(Debug, Clone)]
-            #[cfg_attr(not(feature = "regex_match"), derive(PartialEq, Eq, Hash))]
             pub struct [< $name:camel >] {
                 $(
                     [< $n:snake >] : $t,
@@ -41,18 +57,48 @@ pub trait Expression: Debug {
     fn execute(&self, engine: &mut OperationEngine) -> Result<Option<String>, Error>;
 
     fn clone_dyn(&self) -> Box<dyn Expression>;
+
+    fn is_constant(&self) -> bool {
+        return false;
+    }
 }
 
 pub trait FileOperation: Debug {
     fn execute(&self, engine: &mut OperationEngine) -> Result<bool, Error>;
 
     fn clone_dyn(&self) -> Box<dyn FileOperation>;
+
+    fn mutates_name(&self) -> bool {
+        return false;
+    }
+
+    fn overwrites_name(&self) -> bool {
+        return false;
+    }
+
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+
+        return name.rsplit("::").next().unwrap_or(name);
+    }
 }
 
 pub trait DirOperation: Debug {
     fn execute(&self, engine: &mut OperationEngine, input: &mut Vec<File>) -> Result<(), Error>;
 
     fn clone_dyn(&self) -> Box<dyn DirOperation>;
+
+    /// Run over the directory's files once the file operations have set their
+    /// destinations, instead of before them.
+    fn runs_after_files(&self) -> bool {
+        return false;
+    }
+
+    /// Run after the other operations have filtered and ordered the
+    /// directory's files, but still before the file operations.
+    fn runs_after_ordering(&self) -> bool {
+        return false;
+    }
 }
 
 impl<T> From<T> for Box<dyn Expression>
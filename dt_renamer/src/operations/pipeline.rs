@@ -0,0 +1,468 @@
+use convert_case::Case;
+
+use crate::error::Error;
+use crate::operations::expressions::{
+    AsciiFoldExpr, ConvertCaseExpr, ConvertStemCaseExpr, FileNameExpr, InsertExpr, ReplaceExpr,
+    TitleCaseExpr, ToLowerCaseExpr, ToUpperCaseExpr,
+};
+use crate::operations::file::{SetExtensionOperation, SetNameOperation, SetStemOperation};
+use crate::operations::supporting_objects::{Position, Selection};
+use crate::operations::{Expression, FileOperation};
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(usize),
+    LParen,
+    RParen,
+    Comma,
+    Pipe,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Arg {
+    Ident(String),
+    Str(String),
+    Number(usize),
+}
+
+struct Stage {
+    name: String,
+    position: usize,
+    args: Vec<(Arg, usize)>,
+}
+
+/// Parses a pipe delimited list of stages such as
+/// `lower | replace('_', '-') | insert(end, '.bak')` into file operations, one
+/// per stage, each applied to the current file name.
+///
+/// Supported stages:
+/// - `lower`, `upper`, `title`
+/// - `case(kebab)`, `stem_case(snake)`
+/// - `ascii` or `ascii('_')`
+/// - `replace('find', 'with')` with an optional `first`, `last` or `all`
+/// - `insert(start|end|<index>, 'text')`, `insert(after|before, 'marker', 'text')`
+/// - `set('name')`, `stem('stem')`, `ext('extension')`
+pub fn parse_pipeline(src: &str) -> Result<Vec<Box<dyn FileOperation>>, Error> {
+    let tokens = tokenize(src)?;
+    let mut parser = Parser {
+        tokens,
+        index: 0,
+        end: src.len(),
+    };
+
+    let mut ops = Vec::new();
+
+    loop {
+        let stage = parser.stage()?;
+        ops.push(build_stage(stage)?);
+
+        match parser.next() {
+            None => break,
+            Some((Token::Pipe, _)) => continue,
+            Some((t, p)) => return Err(parse_error(p, format!("expected '|', found {:?}", t))),
+        }
+    }
+
+    return Ok(ops);
+}
+
+fn parse_error(position: usize, message: String) -> Error {
+    return Error::ParseError { position, message };
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '|' => Token::Pipe,
+            '\'' | '"' => {
+                let mut value = String::new();
+                let mut closed = false;
+
+                while let Some((_, n)) = chars.next() {
+                    match n {
+                        n if n == c => {
+                            closed = true;
+                            break;
+                        }
+                        '\\' => match chars.next() {
+                            Some((_, escaped)) => value.push(escaped),
+                            None => break,
+                        },
+                        n => value.push(n),
+                    }
+                }
+
+                if !closed {
+                    return Err(parse_error(i, "unterminated string".to_string()));
+                }
+
+                Token::Str(value)
+            }
+            c if c.is_ascii_digit() => {
+                let mut value = c.to_string();
+
+                while let Some((_, n)) = chars.next_if(|(_, n)| n.is_ascii_digit()) {
+                    value.push(n);
+                }
+
+                Token::Number(
+                    value
+                        .parse()
+                        .map_err(|_| parse_error(i, format!("invalid number '{}'", value)))?,
+                )
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut value = c.to_string();
+
+                while let Some((_, n)) = chars.next_if(|(_, n)| n.is_alphanumeric() || *n == '_') {
+                    value.push(n);
+                }
+
+                Token::Ident(value)
+            }
+            c => return Err(parse_error(i, format!("unexpected character '{}'", c))),
+        };
+
+        tokens.push((token, i));
+    }
+
+    return Ok(tokens);
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+
+        return token;
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.index).map(|(t, _)| t);
+    }
+
+    fn expect_next(&mut self, expected: &str) -> Result<(Token, usize), Error> {
+        return self.next().ok_or_else(|| {
+            parse_error(
+                self.end,
+                format!("expected {}, found end of input", expected),
+            )
+        });
+    }
+
+    fn stage(&mut self) -> Result<Stage, Error> {
+        let (name, position) = match self.expect_next("a stage name")? {
+            (Token::Ident(name), p) => (name, p),
+            (t, p) => {
+                return Err(parse_error(
+                    p,
+                    format!("expected a stage name, found {:?}", t),
+                ))
+            }
+        };
+
+        let mut args = Vec::new();
+
+        if self.peek() != Some(&Token::LParen) {
+            return Ok(Stage {
+                name,
+                position,
+                args,
+            });
+        }
+
+        self.next();
+
+        if self.peek() == Some(&Token::RParen) {
+            self.next();
+
+            return Ok(Stage {
+                name,
+                position,
+                args,
+            });
+        }
+
+        loop {
+            let arg = match self.expect_next("an argument")? {
+                (Token::Ident(v), p) => (Arg::Ident(v), p),
+                (Token::Str(v), p) => (Arg::Str(v), p),
+                (Token::Number(v), p) => (Arg::Number(v), p),
+                (t, p) => {
+                    return Err(parse_error(
+                        p,
+                        format!("expected an argument, found {:?}", t),
+                    ))
+                }
+            };
+
+            args.push(arg);
+
+            match self.expect_next("',' or ')'")? {
+                (Token::Comma, _) => continue,
+                (Token::RParen, _) => break,
+                (t, p) => {
+                    return Err(parse_error(
+                        p,
+                        format!("expected ',' or ')', found {:?}", t),
+                    ))
+                }
+            }
+        }
+
+        return Ok(Stage {
+            name,
+            position,
+            args,
+        });
+    }
+}
+
+impl Stage {
+    fn arity(&self, allowed: &[usize]) -> Result<(), Error> {
+        if allowed.contains(&self.args.len()) {
+            return Ok(());
+        }
+
+        return Err(parse_error(
+            self.position,
+            format!(
+                "'{}' takes {} argument(s), found {}",
+                self.name,
+                allowed
+                    .iter()
+                    .map(|a| a.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                self.args.len()
+            ),
+        ));
+    }
+
+    fn string(&self, i: usize) -> Result<String, Error> {
+        return match &self.args[i] {
+            (Arg::Str(s), _) => Ok(s.clone()),
+            (a, p) => Err(parse_error(*p, format!("expected a string, found {:?}", a))),
+        };
+    }
+
+    fn ident(&self, i: usize) -> Result<(String, usize), Error> {
+        return match &self.args[i] {
+            (Arg::Ident(s), p) => Ok((s.clone(), *p)),
+            (a, p) => Err(parse_error(*p, format!("expected a name, found {:?}", a))),
+        };
+    }
+
+    fn case(&self) -> Result<Case, Error> {
+        let (name, position) = self.ident(0)?;
+
+        return Ok(match name.as_str() {
+            "kebab" => Case::Kebab,
+            "snake" => Case::Snake,
+            "screaming_snake" => Case::ScreamingSnake,
+            "camel" => Case::Camel,
+            "pascal" => Case::Pascal,
+            "title" => Case::Title,
+            "train" => Case::Train,
+            "upper" => Case::Upper,
+            "lower" => Case::Lower,
+            "flat" => Case::Flat,
+            _ => return Err(parse_error(position, format!("unknown case '{}'", name))),
+        });
+    }
+}
+
+fn build_stage(stage: Stage) -> Result<Box<dyn FileOperation>, Error> {
+    let name: Box<dyn Expression> = FileNameExpr::new().into();
+
+    let expr: Box<dyn Expression> = match stage.name.as_str() {
+        "lower" => {
+            stage.arity(&[0])?;
+
+            ToLowerCaseExpr::new(name).into()
+        }
+        "upper" => {
+            stage.arity(&[0])?;
+
+            ToUpperCaseExpr::new(name).into()
+        }
+        "title" => {
+            stage.arity(&[0])?;
+
+            TitleCaseExpr::new(name, Vec::new()).into()
+        }
+        "case" => {
+            stage.arity(&[1])?;
+
+            ConvertCaseExpr::new(stage.case()?, name).into()
+        }
+        "stem_case" => {
+            stage.arity(&[1])?;
+
+            ConvertStemCaseExpr::new(stage.case()?, name).into()
+        }
+        "ascii" => {
+            stage.arity(&[0, 1])?;
+
+            let substitute = match stage.args.is_empty() {
+                true => String::new(),
+                false => stage.string(0)?,
+            };
+
+            AsciiFoldExpr::new(name, substitute).into()
+        }
+        "replace" => {
+            stage.arity(&[2, 3])?;
+
+            let selection = match stage.args.len() {
+                2 => Selection::All,
+                _ => match stage.ident(2)? {
+                    (s, _) if s == "first" => Selection::First,
+                    (s, _) if s == "last" => Selection::Last,
+                    (s, _) if s == "all" => Selection::All,
+                    (s, p) => return Err(parse_error(p, format!("unknown selection '{}'", s))),
+                },
+            };
+
+            ReplaceExpr::new(
+                name,
+                selection,
+                stage.string(0)?.into(),
+                stage.string(1)?.into(),
+            )
+            .into()
+        }
+        "insert" => {
+            stage.arity(&[2, 3])?;
+
+            let position = match (&stage.args[0], stage.args.len()) {
+                ((Arg::Number(n), _), 2) => Position::Index(*n),
+                ((Arg::Ident(p), _), 2) if p == "start" => Position::Start,
+                ((Arg::Ident(p), _), 2) if p == "end" => Position::End,
+                ((Arg::Ident(p), _), 3) if p == "after" => Position::After(stage.string(1)?),
+                ((Arg::Ident(p), _), 3) if p == "before" => Position::Before(stage.string(1)?),
+                ((a, p), _) => {
+                    return Err(parse_error(*p, format!("invalid insert position {:?}", a)))
+                }
+            };
+
+            InsertExpr::new(position, name, stage.string(stage.args.len() - 1)?.into()).into()
+        }
+        "set" => {
+            stage.arity(&[1])?;
+
+            return Ok(SetNameOperation::new(stage.string(0)?.into()).into());
+        }
+        "stem" => {
+            stage.arity(&[1])?;
+
+            return Ok(SetStemOperation::new(stage.string(0)?.into()).into());
+        }
+        "ext" => {
+            stage.arity(&[1])?;
+
+            return Ok(SetExtensionOperation::new(stage.string(0)?.into()).into());
+        }
+        _ => {
+            return Err(parse_error(
+                stage.position,
+                format!("unknown stage '{}'", stage.name),
+            ))
+        }
+    };
+
+    return Ok(SetNameOperation::new(expr).into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OperationEngine;
+
+    fn run(src: &str, name: &str) -> String {
+        return OperationEngine::new(Vec::new(), Vec::new())
+            .transform_name(name, &parse_pipeline(src).unwrap())
+            .unwrap();
+    }
+
+    fn error_position(src: &str) -> usize {
+        return match parse_pipeline(src) {
+            Err(Error::ParseError { position, .. }) => position,
+            Err(e) => panic!("Expected a parse error, got {:?}", e),
+            Ok(_) => panic!("Expected a parse error"),
+        };
+    }
+
+    #[test]
+    fn test_pipeline_1() {
+        assert_eq!(
+            run(
+                "lower | replace('_', '-') | insert(end, '.bak')",
+                "My_File.TXT"
+            ),
+            "my-file.txt.bak"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_2() {
+        assert_eq!(
+            run(
+                "stem_case(kebab) | insert(0, \"2024 \")",
+                "Holiday Photos.JPG"
+            ),
+            "2024 holiday-photos.JPG"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_3() {
+        assert_eq!(
+            run(
+                "ascii | replace('e', 'E', first) | ext('md')",
+                "café notes.txt"
+            ),
+            "cafE notes.md"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_4() {
+        assert_eq!(
+            run("insert(before, '.', '_v2') | upper", "draft.doc"),
+            "DRAFT_V2.DOC"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_error_1() {
+        assert_eq!(error_position("lower | replace('_' '-')"), 20);
+    }
+
+    #[test]
+    fn test_pipeline_error_2() {
+        assert_eq!(error_position("lower | shout"), 8);
+    }
+
+    #[test]
+    fn test_pipeline_error_3() {
+        assert_eq!(error_position("lower |"), 7);
+        assert_eq!(error_position("set('name)"), 4);
+        assert_eq!(error_position("case(loud)"), 5);
+    }
+}
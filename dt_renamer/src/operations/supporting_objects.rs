@@ -12,10 +12,14 @@ pub enum Selection {
 #[cfg_attr(not(feature = "regex_match"), derive(PartialEq, Eq, Hash))]
 pub enum Position {
     Index(usize),
+    IndexFromEnd(usize),
+    EveryN(usize),
     After(String),
+    AfterAll(String),
     #[cfg(feature = "regex_match")]
     AfterRegex(Regex),
     Before(String),
+    BeforeAll(String),
     #[cfg(feature = "regex_match")]
     BeforeRegex(Regex),
     Start,
@@ -30,12 +34,46 @@ pub enum InsertionType {
     Variable(String),
 }
 
+/// What to do with a leading track number, see `TrackNumberExpr`.
+#[cfg(feature = "regex_match")]
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub enum TrackNumberMode {
+    Strip,
+    Pad(usize),
+    Reformat(String),
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
 pub enum SortDirection {
     Ascending,
     Descending,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum MetadataKey {
+    Size,
+    Modified,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Default)]
+pub enum PathMatchMode {
+    #[default]
+    FileName,
+    FullPath,
+}
+
+/// Coarse file type detected from a file's leading bytes.
+#[cfg(feature = "infer")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Category {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Archive,
+    Other,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
 pub enum Direction {
     LeftExclusive,
@@ -1,4 +1,15 @@
-use crate::{error::Error, RenameResult, RenameTree};
+use std::collections::HashMap;
+#[cfg(feature = "serializable")]
+use std::collections::HashSet;
+#[cfg(feature = "serializable")]
+use std::fs::OpenOptions;
+#[cfg(feature = "serializable")]
+use std::io::Write;
+#[cfg(feature = "serializable")]
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::{error::Error, RTBuilder, RenameResult, RenameTree};
 
 #[derive(Debug, Default)]
 pub struct Script {
@@ -20,7 +31,41 @@ impl Script {
         self.trees.push(tree);
     }
 
+    pub fn extend(&mut self, other: Script) {
+        self.trees.extend(other.trees);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.trees.iter().map(|t| t.len()).sum();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.trees.iter().all(|t| t.is_empty());
+    }
+
+    pub fn tree_count(&self) -> usize {
+        return self.trees.len();
+    }
+
+    fn check_collisions(&self) -> Result<(), Error> {
+        let mut claimed: HashMap<&PathBuf, usize> = HashMap::new();
+
+        for (i, tree) in self.trees.iter().enumerate() {
+            for dest in tree.destinations() {
+                if let Some(owner) = claimed.insert(dest, i) {
+                    if owner != i {
+                        return Err(Error::DuplicateDestinationError(dest.display().to_string()));
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
     pub fn run(self) -> Result<Vec<RenameResult>, Error> {
+        self.check_collisions()?;
+
         let mut output = Vec::new();
 
         for res in self.trees.into_iter().map(|m| m.run()) {
@@ -30,6 +75,210 @@ impl Script {
         return Ok(output);
     }
 
+    /// Runs the script, then builds and runs each builder in order so that
+    /// every stage sees the file names produced by the previous one.
+    pub fn run_sequential_rebuild(
+        self,
+        builders: Vec<RTBuilder>,
+    ) -> Result<Vec<RenameResult>, Error> {
+        let mut output = self.run()?;
+
+        for builder in builders {
+            output.append(&mut builder.build_tree()?.run()?);
+        }
+
+        return Ok(output);
+    }
+
+    pub fn run_transactional(self) -> Result<Vec<RenameResult>, Error> {
+        self.check_collisions()?;
+
+        for tree in &self.trees {
+            tree.check_sources()?;
+        }
+
+        let mut journal = Vec::with_capacity(self.len());
+
+        for tree in self.trees {
+            let suffix = tree.temp_suffix();
+
+            if let Err(e) = tree.run_with_fn_into(
+                |source, destination| RenameTree::rename_any_case(source, destination, &suffix),
+                &mut journal,
+            ) {
+                return Err(RenameTree::rollback_with_error(journal, e));
+            }
+        }
+
+        return Ok(journal);
+    }
+
+    #[cfg(feature = "serializable")]
+    pub fn run_logging(self, mut writer: impl Write) -> Result<Vec<RenameResult>, Error> {
+        self.check_collisions()?;
+
+        let mut output = Vec::with_capacity(self.len());
+
+        for tree in self.trees {
+            tree.check_sources()?;
+
+            let suffix = tree.temp_suffix();
+
+            tree.run_with_fn_into(
+                |source, destination| {
+                    let result = RenameTree::rename_any_case(source, destination, &suffix)?;
+
+                    serde_json::to_writer(&mut writer, &result)
+                        .map_err(|e| Error::LogError(e.into()))?;
+                    writeln!(writer)
+                        .and_then(|_| writer.flush())
+                        .map_err(|e| Error::LogError(e))?;
+
+                    return Ok(result);
+                },
+                &mut output,
+            )?;
+        }
+
+        return Ok(output);
+    }
+
+    /// Runs the script, skipping renames that a previous run already recorded
+    /// in the manifest at `path` and whose destination still exists. Completed
+    /// renames are appended to the manifest.
+    #[cfg(feature = "serializable")]
+    pub fn run_with_manifest(mut self, path: &Path) -> Result<Vec<RenameResult>, Error> {
+        let mut manifest: Vec<(PathBuf, PathBuf)> = if path.exists() {
+            let contents = std::fs::read_to_string(path).map_err(|e| Error::ReadFileError(e))?;
+
+            serde_json::from_str(&contents).map_err(|e| Error::InvalidManifest(e.to_string()))?
+        } else {
+            Vec::new()
+        };
+
+        let done: HashSet<(PathBuf, PathBuf)> = manifest.iter().cloned().collect();
+
+        for tree in &mut self.trees {
+            tree.retain(|f| {
+                !(f.destination.exists()
+                    && done.contains(&(f.source.clone(), f.destination.clone())))
+            });
+        }
+
+        self.check_collisions()?;
+
+        let mut output = Vec::with_capacity(self.len());
+        let mut res = Ok(());
+
+        for tree in self.trees {
+            let suffix = tree.temp_suffix();
+
+            res = tree.check_sources().and_then(|_| {
+                tree.run_with_fn_into(
+                    |source, destination| RenameTree::rename_any_case(source, destination, &suffix),
+                    &mut output,
+                )
+            });
+
+            if res.is_err() {
+                break;
+            }
+        }
+
+        manifest.extend(
+            output
+                .iter()
+                .map(|r| (r.source.clone(), r.destination.clone())),
+        );
+
+        let contents =
+            serde_json::to_string(&manifest).map_err(|e| Error::InvalidManifest(e.to_string()))?;
+        std::fs::write(path, contents).map_err(|e| Error::WriteFileError(e))?;
+
+        return res.map(|_| output);
+    }
+
+    /// Runs the script, appending each completed rename to the checkpoint file
+    /// at `checkpoint` as it happens. Re-running after an interruption skips the
+    /// renames already recorded there. An incomplete trailing line left by a
+    /// crash is discarded.
+    #[cfg(feature = "serializable")]
+    pub fn run_resumable(mut self, checkpoint: &Path) -> Result<Vec<RenameResult>, Error> {
+        let done = Self::read_checkpoint(checkpoint)?;
+
+        for tree in &mut self.trees {
+            tree.retain(|f| {
+                !(f.destination.exists()
+                    && done.contains(&(f.source.clone(), f.destination.clone())))
+            });
+        }
+
+        self.check_collisions()?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(checkpoint)
+            .map_err(|e| Error::WriteFileError(e))?;
+
+        let mut output = Vec::with_capacity(self.len());
+
+        for tree in self.trees {
+            tree.check_sources()?;
+
+            let suffix = tree.temp_suffix();
+
+            tree.run_with_fn_into(
+                |source, destination| {
+                    let result = RenameTree::rename_any_case(source, destination, &suffix)?;
+
+                    let line = serde_json::to_string(&(&result.source, &result.destination))
+                        .map_err(|e| Error::InvalidManifest(e.to_string()))?;
+                    writeln!(file, "{}", line)
+                        .and_then(|_| file.sync_data())
+                        .map_err(|e| Error::WriteFileError(e))?;
+
+                    return Ok(result);
+                },
+                &mut output,
+            )?;
+        }
+
+        return Ok(output);
+    }
+
+    #[cfg(feature = "serializable")]
+    fn read_checkpoint(path: &Path) -> Result<HashSet<(PathBuf, PathBuf)>, Error> {
+        if !path.exists() {
+            return Ok(HashSet::new());
+        }
+
+        let contents = std::fs::read_to_string(path).map_err(|e| Error::ReadFileError(e))?;
+        let complete = contents.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+        if complete < contents.len() {
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|f| f.set_len(complete as u64))
+                .map_err(|e| Error::WriteFileError(e))?;
+        }
+
+        let mut done = HashSet::new();
+
+        for line in contents[..complete].lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            done.insert(
+                serde_json::from_str(line).map_err(|e| Error::InvalidManifest(e.to_string()))?,
+            );
+        }
+
+        return Ok(done);
+    }
+
     pub fn dry_run(self) -> Result<Vec<RenameResult>, Error> {
         let mut output = Vec::new();
 
@@ -60,3 +309,245 @@ impl From<RenameTree> for Script {
         return Self { trees: vec![value] };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::directory::IncludeOnlyOperation;
+    use crate::operations::expressions::{FileNameExpr, InsertExpr};
+    use crate::operations::file::SetNameOperation;
+    use crate::operations::supporting_objects::{PathMatchMode, Position};
+    use crate::operations::MatchRule;
+    use crate::{Dir, File, OperationEngine};
+
+    fn tree(renames: &[(&str, &str)]) -> RenameTree {
+        let mut engine = OperationEngine::new(Vec::new(), Vec::new());
+
+        for (source, name) in renames {
+            engine
+                .process_file(File::new(*source).with_op(SetNameOperation::new((*name).into())))
+                .unwrap();
+        }
+
+        return engine.into();
+    }
+
+    #[test]
+    fn test_run_transactional_1() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+
+        std::fs::write(path("a.txt"), "a").unwrap();
+        std::fs::write(path("b.txt"), "b").unwrap();
+        std::fs::write(path("c.txt"), "c").unwrap();
+
+        let script = Script::from([
+            tree(&[(path("a.txt").to_str().unwrap(), "a2.txt")]),
+            tree(&[
+                (path("b.txt").to_str().unwrap(), "b2.txt"),
+                (path("c.txt").to_str().unwrap(), "missing_dir/c2.txt"),
+            ]),
+        ]);
+
+        assert!(matches!(
+            script.run_transactional(),
+            Err(Error::RenameError(_))
+        ));
+
+        assert!(path("a.txt").is_file());
+        assert!(path("b.txt").is_file());
+        assert!(path("c.txt").is_file());
+        assert!(!path("a2.txt").exists());
+        assert!(!path("b2.txt").exists());
+    }
+
+    #[test]
+    fn test_partition_temp_suffix_1() {
+        let dir = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+
+        let script = RTBuilder::new()
+            .with_directory(Dir::new(dir.path(), false))
+            .with_file_op(SetNameOperation::new("b.txt".into()))
+            .temp_suffix(".swap".to_string())
+            .build_tree()
+            .unwrap()
+            .partition(vec![MatchRule::EndsWith(".jpg".to_string())]);
+
+        assert_eq!(script.tree_count(), 2);
+        assert!(script.trees.iter().all(|t| t.temp_suffix() == ".swap"));
+    }
+
+    #[test]
+    fn test_extend_1() {
+        let mut script = Script::from(tree(&[("a/one.txt", "1.txt"), ("a/two.txt", "2.txt")]));
+
+        script.extend(tree(&[("b/three.txt", "3.txt")]).into_script());
+
+        assert_eq!(script.tree_count(), 2);
+        assert_eq!(script.len(), 3);
+        assert!(!script.is_empty());
+    }
+
+    #[test]
+    fn test_is_empty_1() {
+        let script = Script::from(tree(&[]));
+
+        assert_eq!(script.tree_count(), 1);
+        assert!(script.is_empty());
+    }
+
+    #[test]
+    fn test_cross_tree_collision_1() {
+        let script = Script::from([
+            tree(&[("a/one.txt", "1.txt")]),
+            tree(&[("a/two.txt", "1.txt")]),
+        ]);
+
+        assert!(matches!(
+            script.run(),
+            Err(Error::DuplicateDestinationError(_))
+        ));
+    }
+
+    #[cfg(feature = "serializable")]
+    #[test]
+    fn test_run_logging_1() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+
+        std::fs::write(path("a.txt"), "a").unwrap();
+        std::fs::write(path("b.txt"), "b").unwrap();
+
+        let script = Script::from(tree(&[
+            (path("a.txt").to_str().unwrap(), "a2.txt"),
+            (path("b.txt").to_str().unwrap(), "b2.txt"),
+        ]));
+
+        let mut log = Vec::new();
+        let results = script.run_logging(&mut log).unwrap();
+
+        let logged: Vec<RenameResult> = String::from_utf8(log)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        assert_eq!(logged, results);
+        assert_eq!(logged.len(), 2);
+        assert!(path("a2.txt").is_file());
+        assert!(path("b2.txt").is_file());
+    }
+
+    #[test]
+    fn test_run_sequential_rebuild_1() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+
+        std::fs::write(path("a.jpg"), "a").unwrap();
+        std::fs::write(path("b.txt"), "b").unwrap();
+
+        let prefix = |text: &str| {
+            SetNameOperation::new(
+                InsertExpr::new(Position::Start, FileNameExpr::new().into(), text.into()).into(),
+            )
+        };
+
+        let stage_1 =
+            RTBuilder::new().with_directory(Dir::new(dir.path(), false).with_conditional_file_ops(
+                MatchRule::EndsWith(".jpg".to_string()),
+                vec![prefix("img_").into()],
+            ));
+        let stage_2 = RTBuilder::new().with_directory(
+            Dir::new(dir.path(), false)
+                .with_dir_op(IncludeOnlyOperation::new(
+                    MatchRule::BeginsWith("img_".to_string()),
+                    PathMatchMode::FileName,
+                ))
+                .with_file_op(prefix("2024_")),
+        );
+
+        Script::new()
+            .run_sequential_rebuild(vec![stage_1, stage_2])
+            .unwrap();
+
+        assert!(path("2024_img_a.jpg").is_file());
+        assert!(path("b.txt").is_file());
+    }
+
+    #[cfg(feature = "serializable")]
+    #[test]
+    fn test_run_with_manifest_1() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let manifest = path("manifest.json");
+
+        std::fs::write(path("a.txt"), "a").unwrap();
+        std::fs::write(path("b.txt"), "b").unwrap();
+
+        let script = || {
+            Script::from(tree(&[
+                (path("a.txt").to_str().unwrap(), "a2.txt"),
+                (path("b.txt").to_str().unwrap(), "b2.txt"),
+            ]))
+        };
+
+        assert_eq!(script().run_with_manifest(&manifest).unwrap().len(), 2);
+        assert!(path("a2.txt").is_file());
+        assert!(path("b2.txt").is_file());
+
+        assert!(script().run_with_manifest(&manifest).unwrap().is_empty());
+    }
+
+    #[cfg(feature = "serializable")]
+    #[test]
+    fn test_run_resumable_1() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let checkpoint = path("checkpoint.ndjson");
+
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            std::fs::write(path(name), name).unwrap();
+        }
+
+        let script = || {
+            Script::from(tree(&[
+                (path("a.txt").to_str().unwrap(), "a2.txt"),
+                (path("b.txt").to_str().unwrap(), "b2.txt"),
+                (path("c.txt").to_str().unwrap(), "later/c2.txt"),
+                (path("d.txt").to_str().unwrap(), "d2.txt"),
+            ]))
+        };
+
+        // The missing directory interrupts the run after two renames.
+        assert!(matches!(
+            script().run_resumable(&checkpoint),
+            Err(Error::RenameError(_))
+        ));
+        assert!(path("a2.txt").is_file());
+        assert!(path("b2.txt").is_file());
+        assert!(path("c.txt").is_file());
+
+        // Simulate a crash part way through writing a checkpoint line.
+        let mut file = OpenOptions::new().append(true).open(&checkpoint).unwrap();
+        write!(file, "[\"{}", path("c.txt").display()).unwrap();
+
+        std::fs::create_dir(path("later")).unwrap();
+
+        let results = script().run_resumable(&checkpoint).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert!(path("later/c2.txt").is_file());
+        assert!(path("d2.txt").is_file());
+        assert_eq!(
+            std::fs::read_to_string(&checkpoint)
+                .unwrap()
+                .lines()
+                .count(),
+            4
+        );
+
+        assert!(script().run_resumable(&checkpoint).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::error::Error;
+use crate::{Dir, File, RenameResult, RenameTree};
+
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+struct Pending {
+    size: Option<u64>,
+    last_seen: Instant,
+}
+
+impl RenameTree {
+    /// Runs the tree, then watches `dir` and applies the builder's operations to
+    /// files created in it. Each batch of results is passed to `cb`; returning
+    /// `false` stops watching.
+    pub fn watch_run<F>(self, dir: &Path, mut cb: F) -> Result<(), Error>
+    where
+        F: FnMut(Result<Vec<RenameResult>, Error>) -> bool,
+    {
+        let template = self.empty_like();
+
+        let mut produced: HashSet<PathBuf> = self.destinations().cloned().collect();
+
+        if !cb(self.run()) {
+            return Ok(());
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(tx).map_err(|e| Error::WatchError(e.to_string()))?;
+
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| Error::WatchError(e.to_string()))?;
+
+        let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(Ok(event)) => record_event(event, &produced, &mut pending),
+                Ok(Err(e)) => {
+                    if !cb(Err(Error::WatchError(e.to_string()))) {
+                        return Ok(());
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            let ready = take_ready(&mut pending);
+
+            if ready.is_empty() {
+                continue;
+            }
+
+            let mut batch = Dir::new(dir, false);
+            batch.contents = ready.into_iter().map(File::new).collect();
+            batch.processed = true;
+
+            let mut engine = template.engine.for_new_run();
+
+            let res = engine.process_dir(batch).and_then(|_| {
+                let tree = template.with_engine_output(engine);
+
+                produced.extend(tree.destinations().cloned());
+
+                return tree.run();
+            });
+
+            if !cb(res) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn record_event(
+    event: Event,
+    produced: &HashSet<PathBuf>,
+    pending: &mut HashMap<PathBuf, Pending>,
+) {
+    let is_create = matches!(event.kind, EventKind::Create(_));
+
+    if !is_create && !matches!(event.kind, EventKind::Modify(_)) {
+        return;
+    }
+
+    for path in event.paths {
+        if produced.contains(&path) {
+            continue;
+        }
+
+        if let Some(p) = pending.get_mut(&path) {
+            p.last_seen = Instant::now();
+        } else if is_create {
+            pending.insert(
+                path,
+                Pending {
+                    size: None,
+                    last_seen: Instant::now(),
+                },
+            );
+        }
+    }
+}
+
+// A file is only picked up once it has been quiet for a full debounce period
+// and its size has not changed since the previous check.
+fn take_ready(pending: &mut HashMap<PathBuf, Pending>) -> Vec<PathBuf> {
+    let mut ready = Vec::new();
+
+    pending.retain(|path, p| {
+        if p.last_seen.elapsed() < DEBOUNCE {
+            return true;
+        }
+
+        let Ok(meta) = fs::metadata(path) else {
+            return false;
+        };
+
+        if !meta.is_file() {
+            return false;
+        }
+
+        if p.size != Some(meta.len()) {
+            p.size = Some(meta.len());
+            p.last_seen = Instant::now();
+
+            return true;
+        }
+
+        ready.push(path.clone());
+
+        return false;
+    });
+
+    ready.sort();
+
+    return ready;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::file::{IfOperation, SetNameOperation};
+    use crate::operations::MatchRule;
+    use crate::{OverwritePolicy, RTBuilder};
+    use std::thread;
+
+    #[test]
+    fn test_watch_run_1() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        let tree = RTBuilder::new()
+            .with_directory(Dir::new(&root, false))
+            .with_file_op(SetNameOperation::new("renamed.txt".into()))
+            .build_tree()
+            .unwrap();
+
+        let writer_root = root.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            fs::write(writer_root.join("incoming.txt"), "data").unwrap();
+        });
+
+        let mut batches = Vec::new();
+
+        tree.watch_run(&root, |res| {
+            let res = res.unwrap();
+            let done = !res.is_empty();
+
+            batches.push(res);
+
+            return !done;
+        })
+        .unwrap();
+
+        writer.join().unwrap();
+
+        assert_eq!(batches.len(), 2);
+        assert!(batches[0].is_empty());
+        assert_eq!(
+            batches[1][0].destination_path_string().unwrap(),
+            root.join("renamed.txt").display().to_string()
+        );
+        assert!(root.join("renamed.txt").is_file());
+        assert!(!root.join("incoming.txt").exists());
+    }
+
+    #[test]
+    fn test_watch_run_2() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        fs::write(root.join("renamed.txt"), "existing").unwrap();
+
+        let tree = RTBuilder::new()
+            .with_directory(Dir::new(&root, false))
+            .with_file_op(IfOperation::new(
+                MatchRule::Contains("INCOMING".to_string()),
+                Box::new(SetNameOperation::new("renamed.txt".into())),
+                None,
+            ))
+            .case_insensitive(true)
+            .with_overwrite_policy(OverwritePolicy::Rename)
+            .build_tree()
+            .unwrap();
+
+        let writer_root = root.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            fs::write(writer_root.join("incoming.txt"), "data").unwrap();
+        });
+
+        tree.watch_run(&root, |res| {
+            let res = res.unwrap();
+
+            return !res.iter().any(|r| r.source.ends_with("incoming.txt"));
+        })
+        .unwrap();
+
+        writer.join().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(root.join("renamed.txt")).unwrap(),
+            "existing"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("renamed (1).txt")).unwrap(),
+            "data"
+        );
+    }
+}
@@ -10,6 +10,13 @@ pub enum DirProperties {
     Last,
 }
 
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct WalkEntry {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub is_dir: bool,
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct DTWalker {
     root: PathBuf,
@@ -17,6 +24,10 @@ pub struct DTWalker {
     max_depth: usize,
     fail_on_depth: bool,
     canonicalize: bool,
+    extensions: Option<Vec<String>>,
+    excluded_dir_names: Vec<String>,
+    follow_symlinks: bool,
+    empty_dirs: bool,
 }
 
 impl DTWalker {
@@ -27,6 +38,10 @@ impl DTWalker {
             max_depth: usize::MAX,
             fail_on_depth: true,
             canonicalize: false,
+            extensions: None,
+            excluded_dir_names: Vec::new(),
+            follow_symlinks: true,
+            empty_dirs: false,
         };
     }
 
@@ -54,17 +69,80 @@ impl DTWalker {
         return self;
     }
 
+    pub fn with_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = Some(
+            extensions
+                .into_iter()
+                .map(|e| e.trim_start_matches('.').to_lowercase())
+                .collect(),
+        );
+
+        return self;
+    }
+
+    pub fn with_excluded_dir_names(mut self, names: Vec<String>) -> Self {
+        self.excluded_dir_names = names;
+
+        return self;
+    }
+
+    pub fn with_follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+
+        return self;
+    }
+
+    /// Report directories that contain no files even when directories are
+    /// otherwise skipped. `First` and `Last` already include every directory.
+    pub fn with_empty_dirs(mut self, empty_dirs: bool) -> Self {
+        self.empty_dirs = empty_dirs;
+
+        return self;
+    }
+
     pub fn run(self) -> Result<Vec<PathBuf>, Error> {
+        return Ok(self.run_detailed()?.into_iter().map(|e| e.path).collect());
+    }
+
+    pub fn run_detailed(self) -> Result<Vec<WalkEntry>, Error> {
         return self.visit_directory(self.root.clone(), 0);
     }
 
-    fn visit_directory(&self, dir: PathBuf, depth: usize) -> Result<Vec<PathBuf>, Error> {
+    fn canonicalize(&self, path: PathBuf) -> Result<PathBuf, Error> {
+        if self.canonicalize {
+            return path.canonicalize().map_err(|e| Error::CanonicalizeError(e));
+        }
+
+        return Ok(path);
+    }
+
+    fn matches_extension(&self, path: &Path) -> bool {
+        let Some(extensions) = &self.extensions else {
+            return true;
+        };
+
+        return path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| extensions.contains(&e.to_lowercase()));
+    }
+
+    fn is_excluded_dir(&self, path: &Path) -> bool {
+        return path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| self.excluded_dir_names.iter().any(|e| e == n));
+    }
+
+    fn visit_directory(&self, dir: PathBuf, depth: usize) -> Result<Vec<WalkEntry>, Error> {
         if depth >= self.max_depth {
             if self.fail_on_depth {
                 return Err(Error::MaxDepthReached);
             } else {
                 return Ok(match self.directory_inclusions {
-                    DirProperties::First | DirProperties::Last => vec![dir],
+                    DirProperties::First | DirProperties::Last => {
+                        vec![WalkEntry::new(dir, depth, true)]
+                    }
                     DirProperties::Skip => Vec::new(),
                 });
             }
@@ -72,12 +150,9 @@ impl DTWalker {
 
         let mut results = match self.directory_inclusions {
             DirProperties::Skip | DirProperties::Last => Vec::new(),
-            DirProperties::First => vec![if self.canonicalize {
-                dir.canonicalize()
-                    .map_err(|e| Error::CanonicalizeError(e))?
-            } else {
-                dir.clone()
-            }],
+            DirProperties::First => {
+                vec![WalkEntry::new(self.canonicalize(dir.clone())?, depth, true)]
+            }
         };
 
         let contents = read_dir(dir.clone()).map_err(|e| Error::ReadDirError(e))?;
@@ -87,29 +162,191 @@ impl DTWalker {
                 Ok(d) => {
                     let p = d.path();
 
+                    if !self.follow_symlinks
+                        && d.file_type()
+                            .map_err(|e| Error::ReadDirError(e))?
+                            .is_symlink()
+                        && p.is_dir()
+                    {
+                        continue;
+                    }
+
                     if p.is_dir() {
+                        if self.is_excluded_dir(&p) {
+                            continue;
+                        }
+
                         results.extend(self.visit_directory(p, depth + 1)?);
-                    } else if p.is_file() {
-                        results.push(if self.canonicalize {
-                            p.canonicalize().map_err(|e| Error::CanonicalizeError(e))?
-                        } else {
-                            p
-                        });
+                    } else if p.is_file() && self.matches_extension(&p) {
+                        results.push(WalkEntry::new(self.canonicalize(p)?, depth, false));
                     }
                 }
                 Err(e) => return Err(Error::ReadDirError(e)),
             }
         }
 
-        if self.directory_inclusions == DirProperties::Last {
-            results.push(if self.canonicalize {
-                dir.canonicalize()
-                    .map_err(|e| Error::CanonicalizeError(e))?
-            } else {
-                dir.clone()
-            });
+        if self.directory_inclusions == DirProperties::Last
+            || (self.directory_inclusions == DirProperties::Skip
+                && self.empty_dirs
+                && results.iter().all(|e| e.is_dir))
+        {
+            results.push(WalkEntry::new(self.canonicalize(dir)?, depth, true));
         }
 
         return Ok(results);
     }
 }
+
+impl WalkEntry {
+    fn new(path: PathBuf, depth: usize, is_dir: bool) -> Self {
+        return Self {
+            path,
+            depth,
+            is_dir,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_run_detailed_depths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(root.join("a").join("b")).unwrap();
+        fs::write(root.join("top.txt"), "").unwrap();
+        fs::write(root.join("a").join("mid.txt"), "").unwrap();
+        fs::write(root.join("a").join("b").join("low.txt"), "").unwrap();
+
+        let mut entries = DTWalker::new(&root).run_detailed().unwrap();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(
+            entries,
+            vec![
+                WalkEntry::new(root.clone(), 0, true),
+                WalkEntry::new(root.join("a"), 1, true),
+                WalkEntry::new(root.join("a").join("b"), 2, true),
+                WalkEntry::new(root.join("a").join("b").join("low.txt"), 2, false),
+                WalkEntry::new(root.join("a").join("mid.txt"), 1, false),
+                WalkEntry::new(root.join("top.txt"), 0, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_with_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("one.jpg"), "").unwrap();
+        fs::write(root.join("two.txt"), "").unwrap();
+        fs::write(root.join("a").join("three.JPG"), "").unwrap();
+        fs::write(root.join("a").join("four"), "").unwrap();
+
+        let mut paths = DTWalker::new(&root)
+            .with_dir_inclusions(DirProperties::Skip)
+            .with_extensions(vec![".jpg".to_string()])
+            .run()
+            .unwrap();
+        paths.sort();
+
+        assert_eq!(
+            paths,
+            vec![root.join("a").join("three.JPG"), root.join("one.jpg")]
+        );
+    }
+
+    #[test]
+    fn test_excluded_dir_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(root.join("keep")).unwrap();
+        fs::create_dir_all(root.join("skip")).unwrap();
+        fs::write(root.join("keep").join("a.txt"), "").unwrap();
+        fs::write(root.join("skip").join("b.txt"), "").unwrap();
+
+        let paths = DTWalker::new(&root)
+            .with_dir_inclusions(DirProperties::Skip)
+            .with_excluded_dir_names(vec!["skip".to_string()])
+            .run()
+            .unwrap();
+
+        assert_eq!(paths, vec![root.join("keep").join("a.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_without_follow_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(root.join("real")).unwrap();
+        fs::write(root.join("real").join("a.txt"), "").unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("link")).unwrap();
+
+        let paths = DTWalker::new(&root)
+            .with_dir_inclusions(DirProperties::Skip)
+            .with_follow_symlinks(false)
+            .run()
+            .unwrap();
+
+        assert_eq!(paths, vec![root.join("real").join("a.txt")]);
+    }
+
+    #[test]
+    fn test_with_empty_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(root.join("full")).unwrap();
+        fs::create_dir_all(root.join("empty").join("nested")).unwrap();
+        fs::write(root.join("full").join("a.txt"), "").unwrap();
+
+        let mut paths = DTWalker::new(&root)
+            .with_dir_inclusions(DirProperties::Skip)
+            .with_empty_dirs(true)
+            .run()
+            .unwrap();
+        paths.sort();
+
+        assert_eq!(
+            paths,
+            vec![
+                root.join("empty"),
+                root.join("empty").join("nested"),
+                root.join("full").join("a.txt"),
+            ]
+        );
+
+        let paths = DTWalker::new(&root)
+            .with_dir_inclusions(DirProperties::Skip)
+            .run()
+            .unwrap();
+
+        assert_eq!(paths, vec![root.join("full").join("a.txt")]);
+    }
+
+    #[test]
+    fn test_with_empty_dirs_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(root.join("empty")).unwrap();
+
+        let paths = DTWalker::new(&root)
+            .with_dir_inclusions(DirProperties::Last)
+            .with_empty_dirs(true)
+            .run()
+            .unwrap();
+
+        assert_eq!(paths, vec![root.join("empty"), root.clone()]);
+    }
+}
@@ -1,4 +1 @@
-use dt_renamer::{Dir, RTBuilder, RenameTree};
-use regex::Regex;
-
 fn main() {}